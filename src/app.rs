@@ -1,17 +1,18 @@
 use std::sync::Arc;
 use winit::{
-    application::ApplicationHandler, event::*, event_loop::ActiveEventLoop, keyboard::PhysicalKey, window::Window
+    application::ApplicationHandler, event::*, event_loop::ActiveEventLoop, keyboard::PhysicalKey
 };
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::UnwrapThrowExt;
 
-use crate::renderer::State;
+use crate::renderer::{RenderError, State, StateBuilder};
 
 pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<State>>,
     state: Option<State>,
+    builder: StateBuilder, // How the window and the state are created once the app resumes
 }
 
 impl App {
@@ -20,16 +21,30 @@ impl App {
         let proxy = Some(event_loop.create_proxy());
         Self {
             state: None,
+            builder: StateBuilder::default(),
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
     }
+
+    /// Create the window and state from `builder` instead of the defaults
+    pub fn with_state_builder(mut self, builder: StateBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ApplicationHandler<State> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         #[allow(unused_mut)]
-        let mut window_attributes = Window::default_attributes();
+        let mut window_attributes = self.builder.window_attributes();
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -41,8 +56,18 @@ impl ApplicationHandler<State> for App {
             let window = wgpu::web_sys::window().unwrap_throw();
             let document = window.document().unwrap_throw();
             let canvas = document.get_element_by_id(CANVAS_ID).unwrap_throw();
-            let html_canvas_element = canvas.unchecked_into();
-            window_attributes = window_attributes.with_canvas(Some(html_canvas_element));
+            let html_canvas_element: wgpu::web_sys::HtmlCanvasElement = canvas.unchecked_into();
+            // The canvas is laid out in CSS pixels, asking for that logical size makes winit back it
+            // with devicePixelRatio times as many physical pixels so it stays sharp on HiDPI screens
+            let css_size = winit::dpi::LogicalSize::new(
+                html_canvas_element.client_width().max(1) as u32,
+                html_canvas_element.client_height().max(1) as u32,
+            );
+            window_attributes = window_attributes
+                .with_canvas(Some(html_canvas_element))
+                .with_inner_size(css_size);
+            // The page has no title bar of its own, the browser tab shows the document's title
+            document.set_title(&window_attributes.title);
         }
 
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
@@ -51,7 +76,13 @@ impl ApplicationHandler<State> for App {
         {
             // If we are not on web we can use pollster to
             // await the 
-            self.state = Some(pollster::block_on(State::new(window)).unwrap());
+            match pollster::block_on(self.builder.clone().build(window)) {
+                Ok(state) => self.state = Some(state),
+                Err(e) => {
+                    log::error!("Unable to start: {:#}", e);
+                    event_loop.exit();
+                }
+            }
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -59,14 +90,12 @@ impl ApplicationHandler<State> for App {
             // Run the future asynchronously and use the
             // proxy to send the results to the event loop
             if let Some(proxy) = self.proxy.take() {
+                let builder = self.builder.clone();
                 wasm_bindgen_futures::spawn_local(async move {
-                    assert!(proxy
-                        .send_event(
-                            State::new(window)
-                                .await
-                                .expect("Unable to create canvas!!!")
-                        )
-                        .is_ok())
+                    match builder.build(window).await {
+                        Ok(state) => assert!(proxy.send_event(state).is_ok()),
+                        Err(e) => log::error!("Unable to start: {:#}", e),
+                    }
                 });
             }
         }
@@ -78,16 +107,17 @@ impl ApplicationHandler<State> for App {
         #[cfg(target_arch = "wasm32")]
         {
             event.window.request_redraw();
-            event.resize(
-                event.window.inner_size().width,
-                event.window.inner_size().height,
-            );
-            
-
+            event.resize_to_window();
         }
         self.state = Some(event);
     }
 
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state) = self.state.take() {
+            state.destroy(true);
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
@@ -101,17 +131,24 @@ impl ApplicationHandler<State> for App {
 
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
+            // Sizes are in physical pixels, so HiDPI screens get a full resolution surface
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
+            // Moving to a screen with a different DPI changes the physical size without always sending Resized
+            WindowEvent::ScaleFactorChanged { .. } => state.resize_to_window(),
             WindowEvent::RedrawRequested => {
                 state.update();
                 match state.render() {
                     Ok(_) => {},
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
+                    Err(RenderError::Reconfigure(_)) => {
+                        // The window may have changed size too, resize handles that and reconfigure covers the rest
+                        state.resize_to_window();
+                        state.reconfigure();
                     }
-                    Err(e) => {
-                        log::error!("Unable to render {}", e)
+                    // Transient, the next redraw will try again
+                    Err(RenderError::Skip(e)) => log::warn!("Skipping frame: {}", e),
+                    Err(e @ RenderError::Fatal(_)) => {
+                        log::error!("Unable to render, exiting: {}", e);
+                        event_loop.exit();
                     }
                 }
             }
@@ -126,6 +163,29 @@ impl ApplicationHandler<State> for App {
                     state.input(&event);
                     state.handle_key(event_loop, code, key_state.is_pressed());
             }
+            WindowEvent::CursorMoved { position, .. } => state.cursor_moved(position),
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                state.spawn_cube_at_cursor();
+            }
+            // Hold the right button to drag a body around, letting go flings it
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } => {
+                if let Some(handle) = state.grab_at_cursor() {
+                    log::info!("Grabbed body {:?}", handle);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Right,
+                ..
+            } => state.release_grab(),
             _ => {}
         }
     }
@@ -1,10 +1,14 @@
-use cgmath::{self, InnerSpace};
-use bytemuck;
+use cgmath::{self, EuclideanSpace, InnerSpace, Rotation, Rotation3, VectorSpace};
+use rapier3d::prelude::RigidBodyHandle;
 use wgpu::util::DeviceExt;
 
+use crate::input::{Action, KeyBindings};
+use crate::physics::PhysicsWorld;
+
 #[cfg(target_arch = "wasm32")]
 use web_sys::console;
 
+#[derive(Debug, Clone, Copy)]
 pub struct Camera {
     eye: cgmath::Point3<f32>, //position of camera in space
     target: cgmath::Point3<f32>, //where the camera should look at
@@ -13,6 +17,8 @@ pub struct Camera {
     fovy: f32, 
     znear: f32, //clips
     zfar: f32,
+    projection: Projection,
+    debug_logging: bool, // Log matrices to the browser console, off by default since it runs every frame
 }
 
 //webgpu space ranges from 0 to 1 whereas opengl is -1 to 1 
@@ -24,6 +30,33 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+/// How the camera maps camera space onto the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Far things look smaller, using the camera's vertical field of view
+    Perspective,
+    /// No foreshortening, `height` world units fit vertically on screen
+    Orthographic { height: f32 },
+}
+
+// Aspect ratios are clamped to this range so a sliver of a window can't blow up the projection
+const MIN_ASPECT: f32 = 0.01;
+const MAX_ASPECT: f32 = 100.0;
+// Smallest gap allowed between the near and far planes, the projection divides by far - near
+const MIN_DEPTH_RANGE: f32 = 0.001;
+
+/// True if every element of `matrix` is finite (no NaN or infinity)
+pub fn is_matrix_finite(matrix: &cgmath::Matrix4<f32>) -> bool {
+    let array: &[[f32; 4]; 4] = matrix.as_ref();
+    array.iter().flatten().all(|value| value.is_finite())
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Camera {
     pub fn new() -> Self {
         Self {
@@ -36,44 +69,82 @@ impl Camera {
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
+            projection: Projection::Perspective,
+            debug_logging: false,
         }
     }
 
-    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&format!("Building matrix with eye: {:?}, target: {:?}, up: {:?}", 
-            self.eye, self.target, self.up).into());
+    /// Turn the per-frame matrix logging on wasm on or off
+    pub fn set_debug_logging(&mut self, enabled: bool) {
+        self.debug_logging = enabled;
+    }
 
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up); //Create a homogeneous transformation matrix that will cause a vector to point at target from eye, using up for orientation. rh means right handed coordinate system
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&format!("View matrix: {:?}", view).into());
+    pub fn debug_logging(&self) -> bool {
+        self.debug_logging
+    }
 
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar); //have the screen setup with proper aspect ratio and depth without warping
+    /// Log to the browser console if debug logging is on, `message` is only formatted when it's needed
+    fn debug_log(&self, message: impl FnOnce() -> String) {
         #[cfg(target_arch = "wasm32")]
-        console::log_1(&format!("Projection matrix: {:?}", proj).into());
+        if self.debug_logging {
+            console::log_1(&message().into());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = message;
+    }
 
-        let result = OPENGL_TO_WGPU_MATRIX * proj * view;
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&format!("Final matrix: {:?}", result).into());
+    /// World space to camera space
+    pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up) //Create a homogeneous transformation matrix that will cause a vector to point at target from eye, using up for orientation. rh means right handed coordinate system
+    }
+
+    /// Camera space to wgpu clip space, OPENGL_TO_WGPU_MATRIX is already applied
+    pub fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let proj = match self.projection {
+            Projection::Perspective => cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar), //have the screen setup with proper aspect ratio and depth without warping
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+        };
+        OPENGL_TO_WGPU_MATRIX * proj
+    }
+
+    /// `projection_matrix() * view_matrix()`
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.debug_log(|| format!("Building matrix with eye: {:?}, target: {:?}, up: {:?}", 
+            self.eye, self.target, self.up));
+
+        let view = self.view_matrix();
+        self.debug_log(|| format!("View matrix: {:?}", view));
+
+        let proj = self.projection_matrix();
+        self.debug_log(|| format!("Projection matrix: {:?}", proj));
+
+        let result = proj * view;
+        self.debug_log(|| format!("Final matrix: {:?}", result));
         result
     }
 
+    /// The view-projection matrix flattened row by row, see `CameraUniform::as_array`
+    pub fn view_proj_array(&self) -> [f32; 16] {
+        flatten_row_major(self.build_view_projection_matrix().into())
+    }
+
     pub fn reset(&mut self) {
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&"reset being called".into());
+        self.debug_log(|| "reset being called".to_string());
         self.eye = (0.0, 1.0, 2.0).into();
         self.target = (0.0, 0.0, 0.0).into();
         self.up = cgmath::Vector3::unit_y();
     }
 
     pub fn update_aspect(&mut self, width: u32, height: u32) {
-        if height > 0 {
-            self.aspect = width as f32 / height as f32;
-            #[cfg(target_arch = "wasm32")]
-            console::log_1(&format!("Aspect ratio updated: {} / {} = {}", width, height, self.aspect).into());
+        if width > 0 && height > 0 {
+            self.aspect = (width as f32 / height as f32).clamp(MIN_ASPECT, MAX_ASPECT);
+            self.debug_log(|| format!("Aspect ratio updated: {} / {} = {}", width, height, self.aspect));
         } else {
-            #[cfg(target_arch = "wasm32")]
-            console::log_1(&"Warning: Height is 0, keeping current aspect ratio".into());
+            self.debug_log(|| "Warning: Surface has zero area, keeping current aspect ratio".to_string());
         }
     }
 
@@ -100,6 +171,270 @@ impl Camera {
     pub fn set_up(&mut self, up: cgmath::Vector3<f32>) {
         self.up = up;
     }
+
+    pub fn get_fovy(&self) -> f32 {
+        self.fovy
+    }
+
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.fovy = fovy;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Set the clip planes, anything closer than `near` or farther than `far` isn't drawn
+    /// Returns false and keeps the old planes unless 0 < near < far and both are finite
+    pub fn set_near_far(&mut self, near: f32, far: f32) -> bool {
+        if !near.is_finite() || !far.is_finite() || near <= 0.0 || far <= near {
+            return false;
+        }
+        self.znear = near;
+        self.zfar = far.max(near + MIN_DEPTH_RANGE);
+        true
+    }
+
+    pub fn near(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn far(&self) -> f32 {
+        self.zfar
+    }
+
+    /// Ray from the camera through a point on screen in normalized device coordinates
+    /// (-1, -1) is the bottom left corner and (1, 1) the top right
+    pub fn screen_to_ray(&self, ndc_x: f32, ndc_y: f32) -> Option<Ray> {
+        use cgmath::SquareMatrix;
+        let inverse = self.build_view_projection_matrix().invert()?;
+
+        // Unproject the same screen point on the near (depth 0) and far (depth 1) planes
+        let unproject = |depth: f32| {
+            let world = inverse * cgmath::Vector4::new(ndc_x, ndc_y, depth, 1.0);
+            cgmath::Point3::from_homogeneous(world)
+        };
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+
+        let direction = (far - near).normalize();
+        if !direction.x.is_finite() || !direction.y.is_finite() || !direction.z.is_finite() {
+            return None;
+        }
+        Some(Ray { origin: near, direction })
+    }
+
+    /// Pixel position of a world point in a `viewport` of (width, height), with y pointing down
+    /// `None` if the point is behind the camera
+    pub fn world_to_screen(&self, point: cgmath::Point3<f32>, viewport: (u32, u32)) -> Option<(f32, f32)> {
+        let clip = self.build_view_projection_matrix() * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc_x = clip.x / clip.w;
+        let ndc_y = clip.y / clip.w;
+        Some((
+            (ndc_x + 1.0) * 0.5 * viewport.0 as f32,
+            (1.0 - ndc_y) * 0.5 * viewport.1 as f32,
+        ))
+    }
+}
+
+/// A half-line starting at `origin` going along the unit vector `direction`
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    /// Where the ray crosses the horizontal plane y = `height`, if it's heading towards it
+    pub fn intersect_plane_y(&self, height: f32) -> Option<cgmath::Point3<f32>> {
+        if self.direction.y.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = (height - self.origin.y) / self.direction.y;
+        (distance >= 0.0).then(|| self.origin + self.direction * distance)
+    }
+
+    /// Where the ray crosses the plane through `point` facing `normal`, if it's heading towards it
+    pub fn intersect_plane(&self, point: cgmath::Point3<f32>, normal: cgmath::Vector3<f32>) -> Option<cgmath::Point3<f32>> {
+        use cgmath::InnerSpace;
+        let facing = self.direction.dot(normal);
+        if facing.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = (point - self.origin).dot(normal) / facing;
+        (distance >= 0.0).then(|| self.origin + self.direction * distance)
+    }
+}
+
+/// The six planes bounding what a camera can see, used to skip drawing off-screen objects
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    // Each plane is (normal, distance) with the normal pointing into the frustum
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extract the planes from a view-projection matrix (Gribb/Hartmann)
+    /// Expects wgpu clip space, where depth runs from 0 to 1
+    pub fn from_matrix(view_proj: &cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Matrix;
+        let row0 = view_proj.row(0);
+        let row1 = view_proj.row(1);
+        let row2 = view_proj.row(2);
+        let row3 = view_proj.row(3);
+
+        let planes = [
+            row3 + row0, // left
+            row3 - row0, // right
+            row3 + row1, // bottom
+            row3 - row1, // top
+            row2,        // near (z >= 0 in wgpu)
+            row3 - row2, // far
+        ]
+        .map(|plane| {
+            // Normalize so plane distances come out in world units
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+
+        Self { planes }
+    }
+
+    /// Whether any part of a sphere is inside the frustum
+    pub fn contains_sphere(&self, center: cgmath::Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
+/// A saved viewpoint that can be jumped back to
+#[derive(Debug, Clone, Copy)]
+pub struct CameraPreset {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub fovy: f32,
+}
+
+/// Number of camera preset slots
+pub const CAMERA_PRESET_SLOTS: usize = 9;
+
+// What CameraSystem::serialize writes, as a flat JSON object of numbers and number arrays
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SavedCamera {
+    eye: [f32; 3],
+    target: [f32; 3],
+    up: [f32; 3],
+    fovy: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl SavedCamera {
+    fn to_json(self) -> String {
+        let vector = |v: [f32; 3]| format!("[{}, {}, {}]", v[0], v[1], v[2]);
+        format!(
+            "{{\"eye\": {}, \"target\": {}, \"up\": {}, \"fovy\": {}, \"yaw\": {}, \"pitch\": {}}}",
+            vector(self.eye),
+            vector(self.target),
+            vector(self.up),
+            self.fovy,
+            self.yaw,
+            self.pitch,
+        )
+    }
+
+    fn from_json(json: &str) -> anyhow::Result<Self> {
+        let fields = parse_number_object(json)?;
+        let field = |name: &str, len: usize| -> anyhow::Result<&[f32]> {
+            let values = fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, values)| values.as_slice())
+                .ok_or_else(|| anyhow::anyhow!("missing \"{}\"", name))?;
+            if values.len() != len || !values.iter().all(|value| value.is_finite()) {
+                anyhow::bail!("\"{}\" should be {} finite number(s)", name, len);
+            }
+            Ok(values)
+        };
+        let vector = |name: &str| -> anyhow::Result<[f32; 3]> {
+            let values = field(name, 3)?;
+            Ok([values[0], values[1], values[2]])
+        };
+
+        let saved = Self {
+            eye: vector("eye")?,
+            target: vector("target")?,
+            up: vector("up")?,
+            fovy: field("fovy", 1)?[0],
+            yaw: field("yaw", 1)?[0],
+            pitch: field("pitch", 1)?[0],
+        };
+        if saved.eye == saved.target {
+            anyhow::bail!("eye and target are the same point");
+        }
+        if !(saved.fovy > 0.0 && saved.fovy < 180.0) {
+            anyhow::bail!("fovy {} is out of range", saved.fovy);
+        }
+        Ok(saved)
+    }
+}
+
+// Parse `{"key": 1.5, "other": [1, 2, 3], ...}`, the only shape SavedCamera needs
+// Single numbers come back as one-element lists
+fn parse_number_object(json: &str) -> anyhow::Result<Vec<(String, Vec<f32>)>> {
+    let body = json
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or_else(|| anyhow::anyhow!("expected a JSON object"))?;
+
+    let mut fields = Vec::new();
+    let mut rest = body.trim();
+    while !rest.is_empty() {
+        let after_quote = rest.strip_prefix('"').ok_or_else(|| anyhow::anyhow!("expected a quoted key"))?;
+        let (key, after_key) = after_quote.split_once('"').ok_or_else(|| anyhow::anyhow!("unterminated key"))?;
+        let after_colon = after_key
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| anyhow::anyhow!("expected ':' after \"{}\"", key))?
+            .trim_start();
+
+        let (value, after_value) = match after_colon.strip_prefix('[') {
+            Some(list) => {
+                let (items, after) = list.split_once(']').ok_or_else(|| anyhow::anyhow!("unterminated list"))?;
+                (items, after)
+            }
+            None => after_colon.split_at(after_colon.find(',').unwrap_or(after_colon.len())),
+        };
+        let values = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse::<f32>().map_err(|_| anyhow::anyhow!("\"{}\" is not a number", item)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        fields.push((key.to_string(), values));
+
+        rest = after_value.trim_start();
+        if let Some(after_comma) = rest.strip_prefix(',') {
+            rest = after_comma.trim_start();
+        } else if !rest.is_empty() {
+            anyhow::bail!("expected ',' after \"{}\"", key);
+        }
+    }
+    Ok(fields)
 }
 
 // We need this for Rust to store our data correctly for the shaders
@@ -112,6 +447,12 @@ pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraUniform {
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
@@ -120,40 +461,123 @@ impl CameraUniform {
         }
     }
 
-    pub fn update_view_proj(&mut self, camera: &Camera) {
+    /// The uploaded matrix flattened row by row, so element `[row * 4 + col]`
+    /// Right-handed, y up, and clip space depth runs 0..1 (wgpu) rather than OpenGL's -1..1
+    /// Column-major consumers (WebGL, gl-matrix) need to transpose it
+    pub fn as_array(&self) -> [f32; 16] {
+        flatten_row_major(self.view_proj)
+    }
+
+    /// Recompute the matrix from `camera`, returns false (keeping the previous matrix) if it isn't finite
+    /// e.g. when eye and target coincide or up is parallel to the view direction
+    pub fn update_view_proj(&mut self, camera: &Camera) -> bool {
         let matrix = camera.build_view_projection_matrix();
-        self.view_proj = matrix.into();
-        
+
         // Debug: Check matrix values
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&format!("View-projection matrix: {:?}", matrix).into());
+        camera.debug_log(|| format!("View-projection matrix: {:?}", matrix));
+
+        if !is_matrix_finite(&matrix) {
+            return false;
+        }
+        self.view_proj = matrix.into();
+        true
     }
 }
 
+// `columns` is cgmath's layout, one inner array per column
+fn flatten_row_major(columns: [[f32; 4]; 4]) -> [f32; 16] {
+    let mut flat = [0.0; 16];
+    for (col, column) in columns.iter().enumerate() {
+        for (row, value) in column.iter().enumerate() {
+            flat[row * 4 + col] = *value;
+        }
+    }
+    flat
+}
+
+// Degrees per second the roll keys turn the camera
+const ROLL_SPEED: f32 = 90.0;
+
+// Units per second the controller moves at unless told otherwise
+const DEFAULT_CAMERA_SPEED: f32 = 6.0;
+
+#[derive(Debug, Clone)]
 pub struct CameraController {
-    speed: f32,
+    speed: f32, // Units per second
+    sprint_multiplier: f32, // Applied to speed while sprint is held
+    is_sprint_pressed: bool,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
     // Camera orientation
     yaw: f32,   // Horizontal rotation (left/right)
     pitch: f32, // Vertical rotation (up/down)
+    roll: f32,  // Rotation around the view direction in degrees, positive banks to the right
+    is_roll_left_pressed: bool,
+    is_roll_right_pressed: bool,
+    // Where input wants the camera to be, the actual camera eases towards these
+    goal_eye: Option<cgmath::Point3<f32>>, // None until picked up from the camera on the next update
+    goal_target: Option<cgmath::Point3<f32>>,
+    smoothing: f32, // Exponential smoothing rate per second, 0 snaps straight to the goal
+    key_bindings: KeyBindings,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAMERA_SPEED)
+    }
 }
 
 impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
+            sprint_multiplier: 4.0,
+            is_sprint_pressed: false,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
             yaw: -90.0, // Start looking along negative z-axis
             pitch: 0.0,
+            roll: 0.0,
+            is_roll_left_pressed: false,
+            is_roll_right_pressed: false,
+            goal_eye: None,
+            goal_target: None,
+            smoothing: 15.0,
+            key_bindings: KeyBindings::default(),
         }
     }
 
+    /// Set the movement speed in units per second
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Set how many times faster the camera moves while sprint (shift) is held
+    pub fn set_sprint_multiplier(&mut self, multiplier: f32) {
+        self.sprint_multiplier = multiplier.max(0.0);
+    }
+
+    pub fn sprint_multiplier(&self) -> f32 {
+        self.sprint_multiplier
+    }
+
+    /// Change which keys move the camera
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+
     pub fn process_events(&mut self, event: &winit::event::WindowEvent) -> bool {
         match event {
             winit::event::WindowEvent::KeyboardInput {
@@ -166,24 +590,44 @@ impl CameraController {
                 ..
             } => {
                 let is_pressed = *state == winit::event::ElementState::Pressed;
-                match keycode {
-                    winit::keyboard::KeyCode::KeyW | winit::keyboard::KeyCode::ArrowUp => {
+                match self.key_bindings.action_for(*keycode) {
+                    Some(Action::Forward) => {
                         self.is_forward_pressed = is_pressed;
                         true
                     }
-                    winit::keyboard::KeyCode::KeyA | winit::keyboard::KeyCode::ArrowLeft => {
+                    Some(Action::Left) => {
                         self.is_left_pressed = is_pressed;
                         true
                     }
-                    winit::keyboard::KeyCode::KeyS | winit::keyboard::KeyCode::ArrowDown => {
+                    Some(Action::Back) => {
                         self.is_backward_pressed = is_pressed;
                         true
                     }
-                    winit::keyboard::KeyCode::KeyD | winit::keyboard::KeyCode::ArrowRight => {
+                    Some(Action::Right) => {
                         self.is_right_pressed = is_pressed;
                         true
                     }
-                    winit::keyboard::KeyCode::KeyR => {
+                    Some(Action::Up) => {
+                        self.is_up_pressed = is_pressed;
+                        true
+                    }
+                    Some(Action::Down) => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    }
+                    Some(Action::RollLeft) => {
+                        self.is_roll_left_pressed = is_pressed;
+                        true
+                    }
+                    Some(Action::RollRight) => {
+                        self.is_roll_right_pressed = is_pressed;
+                        true
+                    }
+                    Some(Action::Sprint) => {
+                        self.is_sprint_pressed = is_pressed;
+                        true
+                    }
+                    Some(Action::Reset) => {
                         if is_pressed {
                             self.reset_orientation();
                         }
@@ -196,10 +640,8 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::{InnerSpace, Rad, Deg};
-        
-        // Calculate camera direction from yaw and pitch
+    /// Direction the camera faces, from yaw and pitch
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
         let yaw_rad = cgmath::Rad::from(cgmath::Deg(self.yaw));
         let pitch_rad = cgmath::Rad::from(cgmath::Deg(self.pitch));
         
@@ -208,40 +650,128 @@ impl CameraController {
         let forward_y = pitch_rad.0.sin();
         let forward_z = yaw_rad.0.sin() * pitch_rad.0.cos();
         
-        let forward = cgmath::Vector3::new(forward_x, forward_y, forward_z).normalize();
+        cgmath::Vector3::new(forward_x, forward_y, forward_z).normalize()
+    }
+
+    /// Move the goal position from input, then ease the camera towards it
+    /// `dt` is the frame time in seconds
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        // Calculate camera direction from yaw and pitch
+        let forward = self.forward();
         
-        // Calculate right direction (perpendicular to forward and up)
+        // Right comes from yaw alone rather than forward x world up, so it stays defined looking straight up or down
         let up = cgmath::Vector3::unit_y();
-        let right = forward.cross(up).normalize();
-        
-        // Calculate up direction (perpendicular to forward and right)
-        let camera_up = right.cross(forward).normalize();
+        let yaw_rad = self.yaw.to_radians();
+        let right = cgmath::Vector3::new(-yaw_rad.sin(), 0.0, yaw_rad.cos());
+
+        if self.is_roll_left_pressed {
+            self.roll -= ROLL_SPEED * dt;
+        }
+        if self.is_roll_right_pressed {
+            self.roll += ROLL_SPEED * dt;
+        }
+
+        // Up is perpendicular to forward and right, then rolled around the view direction
+        let level_up = right.cross(forward).normalize();
+        let roll = cgmath::Quaternion::from_axis_angle(forward, cgmath::Deg(self.roll));
+        let camera_up = roll.rotate_vector(level_up);
         
-        // Update camera position based on input
-        let mut new_eye = camera.get_eye();
+        // Update goal position based on input
+        let mut new_eye = self.goal_eye.unwrap_or_else(|| camera.get_eye());
         
+        // Speed is in units per second, so scale by the frame time
+        let speed = if self.is_sprint_pressed { self.speed * self.sprint_multiplier } else { self.speed };
+        let step = speed * dt;
         if self.is_forward_pressed {
-            new_eye += forward * self.speed;
+            new_eye += forward * step;
         }
         if self.is_backward_pressed {
-            new_eye -= forward * self.speed;
+            new_eye -= forward * step;
         }
         if self.is_right_pressed {
-            new_eye += right * self.speed;
+            new_eye += right * step;
         }
         if self.is_left_pressed {
-            new_eye -= right * self.speed;
+            new_eye -= right * step;
+        }
+        // Vertical movement follows world up rather than the camera, so looking down doesn't turn it into forward motion
+        if self.is_up_pressed {
+            new_eye += up * step;
+        }
+        if self.is_down_pressed {
+            new_eye -= up * step;
         }
         
+        let new_target = new_eye + forward;
+        self.goal_eye = Some(new_eye);
+        self.goal_target = Some(new_target);
+
+        // Frame-rate independent exponential smoothing towards the goal
+        let t = if self.smoothing > 0.0 {
+            1.0 - (-self.smoothing * dt).exp()
+        } else {
+            1.0
+        };
+        let eye = camera.get_eye().to_vec().lerp(new_eye.to_vec(), t);
+        let target = camera.get_target().to_vec().lerp(new_target.to_vec(), t);
+
         // Update camera
-        camera.set_eye(new_eye);
-        camera.set_target(new_eye + forward);
+        camera.set_eye(cgmath::Point3::from_vec(eye));
+        camera.set_target(cgmath::Point3::from_vec(target));
         camera.set_up(camera_up);
     }
 
+    /// Set how quickly the camera eases towards where input moved it (per second)
+    /// Higher is snappier, 0 disables smoothing
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.max(0.0);
+    }
+
+    pub fn smoothing(&self) -> f32 {
+        self.smoothing
+    }
+
+    /// Set yaw and pitch so the camera looks from `eye` towards `target`
+    /// update_camera rebuilds the target from yaw/pitch every frame, so this keeps an explicit target from being overwritten
+    pub fn look_at(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        let direction = target - eye;
+        if direction.magnitude2() > 0.0 {
+            let direction = direction.normalize();
+            // Stay off the poles, where the right vector in update_camera degenerates
+            self.pitch = direction.y.asin().to_degrees().clamp(-89.0, 89.0);
+            self.yaw = direction.z.atan2(direction.x).to_degrees();
+        }
+        // Jump straight there rather than easing from the old goal
+        self.goal_eye = Some(eye);
+        self.goal_target = Some(target);
+    }
+
+    /// Set the roll so `update_camera` produces `up` at the current yaw and pitch
+    /// Only the part of `up` across the view direction can be reached by rolling
+    pub fn roll_towards(&mut self, up: cgmath::Vector3<f32>) {
+        let forward = self.forward();
+        let yaw_rad = self.yaw.to_radians();
+        let right = cgmath::Vector3::new(-yaw_rad.sin(), 0.0, yaw_rad.cos());
+        let level_up = right.cross(forward).normalize();
+        let up = up - forward * up.dot(forward);
+        if up.magnitude2() > 0.0 {
+            self.roll = level_up.cross(up).dot(forward).atan2(level_up.dot(up)).to_degrees();
+        }
+    }
+
     pub fn reset_orientation(&mut self) {
         self.yaw = -90.0;
         self.pitch = 0.0;
+        self.roll = 0.0;
+    }
+
+    /// Set the roll in degrees, positive banks the view to the right
+    pub fn set_roll(&mut self, roll: f32) {
+        self.roll = roll;
+    }
+
+    pub fn roll(&self) -> f32 {
+        self.roll
     }
 }
 
@@ -254,12 +784,54 @@ pub struct CameraSystem {
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Body the camera is chasing, None for free-fly
+    follow_target: Option<RigidBodyHandle>,
+    // Where the eye sits relative to the followed body
+    follow_offset: cgmath::Vector3<f32>,
+    // Fraction of the remaining distance covered every 1/60 s while following (0..1]
+    follow_smoothing: f32,
+    presets: [Option<CameraPreset>; CAMERA_PRESET_SLOTS],
+    preset_saved_at: [u64; CAMERA_PRESET_SLOTS], // When each slot was last saved, for finding the oldest
+    presets_saved: u64,
+    current_preset: Option<usize>, // Last preset loaded, where cycling continues from
+    // The perspective camera to go back to while the top-down view is on
+    top_down_previous: Option<Camera>,
+    shake: Option<Shake>,
 }
 
+// A decaying wobble added to the eye and target when the uniform is written, never stored in `camera`
+#[derive(Debug, Clone, Copy)]
+struct Shake {
+    intensity: f32, // Largest offset in world units, at the start
+    duration: f32, // Seconds
+    elapsed: f32,
+}
+
+impl Shake {
+    // Strength left after `elapsed`, fading out quadratically
+    fn amplitude(&self) -> f32 {
+        let left = (1.0 - self.elapsed / self.duration).clamp(0.0, 1.0);
+        self.intensity * left * left
+    }
+
+    // Smooth pseudo-random offset, sums of sines at unrelated frequencies so it doesn't visibly repeat
+    // It only depends on the elapsed time, so the shake looks the same at any frame rate
+    fn offset(&self, phase: f32) -> cgmath::Vector3<f32> {
+        let t = self.elapsed * 40.0 + phase;
+        let noise = |a: f32, b: f32| ((t * a).sin() + (t * b + 1.3).sin()) * 0.5;
+        cgmath::Vector3::new(noise(1.0, 2.31), noise(1.37, 2.93), noise(0.83, 3.17)) * self.amplitude()
+    }
+}
+
+// Space left around the scene in the top-down view, as a fraction of its size
+const TOP_DOWN_MARGIN: f32 = 0.1;
+// How far above the highest body the top-down eye sits
+const TOP_DOWN_CLEARANCE: f32 = 10.0;
+
 impl CameraSystem {
     /// Create a new camera system with default settings
     pub fn new(device: &wgpu::Device) -> Self {
-        let mut camera_controller = CameraController::new(0.1); // Increased speed for better responsiveness
+        let camera_controller = CameraController::default();
         
         // Initialize camera with proper orientation
         let mut camera = Camera::new();
@@ -269,15 +841,7 @@ impl CameraSystem {
         camera.set_eye(initial_position);
         
         // Calculate initial target based on yaw and pitch
-        let yaw_rad = cgmath::Rad::from(cgmath::Deg(camera_controller.yaw));
-        let pitch_rad = cgmath::Rad::from(cgmath::Deg(camera_controller.pitch));
-        
-        let forward_x = yaw_rad.0.cos() * pitch_rad.0.cos();
-        let forward_y = pitch_rad.0.sin();
-        let forward_z = yaw_rad.0.sin() * pitch_rad.0.cos();
-        
-        let forward = cgmath::Vector3::new(forward_x, forward_y, forward_z).normalize();
-        let target_position = initial_position + forward;
+        let target_position = initial_position + camera_controller.forward();
         camera.set_target(target_position);
 
         let mut camera_uniform = CameraUniform::new();
@@ -325,24 +889,184 @@ impl CameraSystem {
             camera_buffer,
             camera_bind_group,
             camera_bind_group_layout,
+            follow_target: None,
+            follow_offset: cgmath::Vector3::new(0.0, 3.0, 8.0),
+            follow_smoothing: 0.1,
+            presets: [None; CAMERA_PRESET_SLOTS],
+            preset_saved_at: [0; CAMERA_PRESET_SLOTS],
+            presets_saved: 0,
+            current_preset: None,
+            top_down_previous: None,
+            shake: None,
         }
     }
 
+    /// Upload the current view-projection matrix
+    /// A non-finite matrix is never uploaded, the GPU keeps the last good one instead of drawing nothing
+    fn write_uniform(&mut self, queue: &wgpu::Queue) {
+        let mut camera = self.camera;
+        if let Some(shake) = self.shake {
+            // Eye and target move differently, so the view turns a little as well as moving
+            camera.set_eye(camera.eye + shake.offset(0.0));
+            camera.set_target(camera.target + shake.offset(17.0) * 0.5);
+        }
+        if self.camera_uniform.update_view_proj(&camera) {
+            queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        }
+    }
+
+    /// Log camera matrices to the browser console (wasm only), off by default
+    pub fn set_debug_logging(&mut self, enabled: bool) {
+        self.camera.set_debug_logging(enabled);
+    }
+
     /// Update camera aspect ratio when window is resized
     pub fn update_aspect(&mut self, width: u32, height: u32) {
         self.camera.update_aspect(width, height);
     }
 
     /// Update camera controller and uniform data
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        // Update camera based on controller input
-        self.camera_controller.update_camera(&mut self.camera);
-        
-        // Update camera uniform with new view-projection matrix
-        self.camera_uniform.update_view_proj(&self.camera);
+    /// `dt` is the frame time in seconds, returns true if the view changed
+    pub fn update(&mut self, queue: &wgpu::Queue, world: &PhysicsWorld, dt: f32) -> bool {
+        let previous_view_proj = self.camera_uniform.view_proj;
+
+        match self.follow_target.and_then(|handle| world.get_body(handle)) {
+            // The top-down view stays put until it's toggled off
+            _ if self.top_down_previous.is_some() => {}
+            // Chase the followed body instead of flying
+            Some(body) => self.update_follow(cgmath::Point3::from_vec(body.position), dt),
+            // Update camera based on controller input
+            None => self.camera_controller.update_camera(&mut self.camera, dt),
+        }
+
+        if let Some(shake) = &mut self.shake {
+            shake.elapsed += dt;
+            if shake.elapsed >= shake.duration {
+                self.shake = None;
+            }
+        }
         
-        // Write updated uniform data to GPU buffer
-        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        // Update camera uniform with new view-projection matrix and write it to the GPU buffer
+        self.write_uniform(queue);
+        self.camera_uniform.view_proj != previous_view_proj
+    }
+
+    /// Shake the view by up to `intensity` world units, fading out over `duration` seconds
+    /// The offset is only added to the uploaded matrix, the camera itself and its controller don't move
+    /// A weaker shake doesn't cut short a stronger one that's still running
+    pub fn add_shake(&mut self, intensity: f32, duration: f32) {
+        if !(intensity > 0.0 && duration > 0.0) {
+            return;
+        }
+        let current = self.shake.map_or(0.0, |shake| shake.amplitude());
+        if intensity >= current {
+            self.shake = Some(Shake { intensity, duration, elapsed: 0.0 });
+        }
+    }
+
+    /// True while a shake from `add_shake` is still running
+    pub fn is_shaking(&self) -> bool {
+        self.shake.is_some()
+    }
+
+    /// Switch between the current view and an orthographic view looking straight down at every body
+    /// Returns true if the top-down view is now on. Toggling it off restores the previous camera
+    pub fn toggle_top_down(&mut self, world: &PhysicsWorld, queue: &wgpu::Queue) -> bool {
+        if let Some(previous) = self.top_down_previous.take() {
+            // Keep the aspect, the window may have been resized in the meantime
+            let aspect = self.camera.aspect;
+            self.camera = previous;
+            self.camera.aspect = aspect;
+            self.write_uniform(queue);
+            return false;
+        }
+        self.top_down_previous = Some(self.camera);
+
+        // Bounds of every body, or a patch around the origin in an empty scene
+        let mut min = cgmath::Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = cgmath::Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for body in world.get_bodies().values() {
+            let half = body.shape.size() / 2.0;
+            for axis in 0..3 {
+                min[axis] = min[axis].min(body.position[axis] - half[axis]);
+                max[axis] = max[axis].max(body.position[axis] + half[axis]);
+            }
+        }
+        if min.x > max.x {
+            min = cgmath::Vector3::new(-5.0, 0.0, -5.0);
+            max = cgmath::Vector3::new(5.0, 0.0, 5.0);
+        }
+        let center = (min + max) / 2.0;
+
+        // Looking down -y, world up can't be the up vector, so +z is up on screen and x runs sideways
+        let width = (max.x - min.x).max(1.0);
+        let depth = (max.z - min.z).max(1.0);
+        let height = depth.max(width / self.camera.aspect) * (1.0 + TOP_DOWN_MARGIN);
+        let eye = cgmath::Point3::new(center.x, max.y + TOP_DOWN_CLEARANCE, center.z);
+        let target = cgmath::Point3::new(center.x, min.y, center.z);
+        self.camera.set_eye(eye);
+        self.camera.set_target(target);
+        self.camera.set_up(cgmath::Vector3::unit_z());
+        self.camera.set_projection(Projection::Orthographic { height });
+        // Everything down to the lowest body has to fit between the clip planes
+        let far = self.camera.far().max(eye.y - min.y + TOP_DOWN_CLEARANCE);
+        self.camera.set_near_far(self.camera.near(), far);
+        self.write_uniform(queue);
+        true
+    }
+
+    pub fn is_top_down(&self) -> bool {
+        self.top_down_previous.is_some()
+    }
+
+    /// Chase a body with the camera, or pass `None` to go back to free-fly
+    pub fn follow(&mut self, handle: Option<RigidBodyHandle>) {
+        self.follow_target = handle;
+    }
+
+    /// The body the camera is currently following, if any
+    pub fn follow_target(&self) -> Option<RigidBodyHandle> {
+        self.follow_target
+    }
+
+    /// Jump the camera to look at a body from `distance` away, keeping the current view direction
+    /// A one-shot move, unlike `follow`. Returns false if the body doesn't exist
+    pub fn look_at_body(&mut self, world: &PhysicsWorld, handle: RigidBodyHandle, distance: f32, queue: &wgpu::Queue) -> bool {
+        let Some(body) = world.get_body(handle) else {
+            return false;
+        };
+        let target = cgmath::Point3::from_vec(body.position);
+        let eye = target - self.camera_controller.forward() * distance;
+        self.look_at(eye, target, queue);
+        true
+    }
+
+    /// Set where the eye sits relative to the followed body
+    pub fn set_follow_offset(&mut self, offset: cgmath::Vector3<f32>) {
+        self.follow_offset = offset;
+    }
+
+    /// Set how quickly the camera catches up with the followed body, 1.0 snaps instantly
+    /// This is the fraction of the remaining distance covered every 1/60 s, whatever the frame rate
+    pub fn set_follow_smoothing(&mut self, smoothing: f32) {
+        self.follow_smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    /// Move the camera part of the way towards its follow position so bounces don't jitter the view
+    fn update_follow(&mut self, body_position: cgmath::Point3<f32>, dt: f32) {
+        let desired_eye = body_position + self.follow_offset;
+        // Scale the per-frame fraction by dt so the catch-up speed doesn't depend on the frame rate
+        let t = 1.0 - (1.0 - self.follow_smoothing).powf(dt * 60.0);
+
+        let eye = self.camera.get_eye().to_vec().lerp(desired_eye.to_vec(), t);
+        let target = self.camera.get_target().to_vec().lerp(body_position.to_vec(), t);
+        let eye = cgmath::Point3::from_vec(eye);
+        let target = cgmath::Point3::from_vec(target);
+
+        self.camera.set_eye(eye);
+        self.camera.set_target(target);
+        // Keep the controller pointing the same way so free-fly resumes from here
+        self.camera_controller.look_at(eye, target);
     }
 
     /// Process window events for camera input
@@ -376,10 +1100,10 @@ impl CameraSystem {
             max_z = max_z.max(instance.position.z);
         }
 
-        // Calculate center (ignore z for camera positioning as requested)
+        // Center of the bounding box, so a grid of any size is framed around its middle
         let center_x = (min_x + max_x) / 2.0;
         let center_y = (min_y + max_y) / 2.0;
-        let center_z = 10.0; // Set to ground level as requested
+        let center_z = (min_z + max_z) / 2.0;
 
         cgmath::Point3::new(center_x, center_y, center_z)
     }
@@ -389,41 +1113,142 @@ impl CameraSystem {
     pub fn position_camera_at_instances_center(&mut self, instances: &[Instance], queue: &wgpu::Queue) {
         let center = self.calculate_instances_center(instances);
         
-        // Calculate the largest magnitude for x,y to determine camera distance
-        let max_magnitude = instances.iter()
-            .map(|instance| {
-                let dx = (instance.position.x - center.x).abs();
-                let dy = (instance.position.y - center.y).abs();
-                dx.max(dy)
-            })
-            .fold(0.0, f32::max);
-
-        // Set camera position: offset from center with appropriate height
-        let camera_distance = (max_magnitude * 3.0).max(5.0); // At least 5 units away
-        let camera_height = 3.0; // Fixed height above ground
+        // Radius of a sphere around every instance, with some room for the instances' own size
+        let radius = instances.iter()
+            .map(|instance| (instance.position - center.to_vec()).magnitude())
+            .fold(0.0, f32::max)
+            + 1.0;
+
+        // Back off until that sphere fits in the vertical field of view, looking down at the scene a little
+        let half_fov = cgmath::Rad::from(cgmath::Deg(self.camera.fovy / 2.0)).0;
+        let camera_distance = (radius / half_fov.sin()).max(5.0); // At least 5 units away
+        let direction = cgmath::Vector3::new(0.0, 0.5, 1.0).normalize();
         
-        self.camera.set_eye(cgmath::Point3::new(
-            center.x,
-            center.y + camera_height,
-            center.z + camera_distance
-        ));
+        self.camera.set_eye(center + direction * camera_distance);
         
         // Set target to the center
         self.camera.set_target(center);
+        self.camera_controller.look_at(self.camera.get_eye(), center);
         
         // Update camera uniform and GPU buffer
-        self.camera_uniform.update_view_proj(&self.camera);
-        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        self.write_uniform(queue);
+    }
+
+    /// Set the camera's clip planes and update the GPU buffer, see `Camera::set_near_far`
+    pub fn set_near_far(&mut self, near: f32, far: f32, queue: &wgpu::Queue) -> bool {
+        if !self.camera.set_near_far(near, far) {
+            return false;
+        }
+        self.write_uniform(queue);
+        true
+    }
+
+    /// Place the camera at `eye` looking at `target` and update GPU buffer
+    pub fn look_at(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>, queue: &wgpu::Queue) {
+        self.camera.set_eye(eye);
+        self.camera.set_target(target);
+        self.camera_controller.look_at(eye, target);
+
+        self.write_uniform(queue);
+    }
+
+    /// The camera as a JSON string, for restoring it with `deserialize` in a later session
+    /// While the top-down view is on the camera it returns to is saved instead
+    pub fn serialize(&self) -> String {
+        let camera = self.top_down_previous.as_ref().unwrap_or(&self.camera);
+        SavedCamera {
+            eye: camera.get_eye().into(),
+            target: camera.get_target().into(),
+            up: camera.get_up().into(),
+            fovy: camera.get_fovy(),
+            yaw: self.camera_controller.yaw,
+            pitch: self.camera_controller.pitch,
+        }
+        .to_json()
+    }
+
+    /// Restore a camera saved by `serialize`
+    /// Malformed or invalid data is an error and leaves the camera as it was
+    pub fn deserialize(&mut self, json: &str, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        let saved = SavedCamera::from_json(json)?;
+        self.top_down_previous = None;
+        self.camera.set_projection(Projection::Perspective);
+        self.camera.set_up(saved.up.into());
+        self.camera.set_fovy(saved.fovy);
+        self.look_at(saved.eye.into(), saved.target.into(), queue);
+        // look_at derives these from the target, the saved ones keep the exact heading
+        self.camera_controller.yaw = saved.yaw;
+        self.camera_controller.pitch = saved.pitch.clamp(-89.0, 89.0);
+        Ok(())
+    }
+
+    /// Save the current viewpoint into `slot`, returns false if the slot doesn't exist
+    pub fn save_preset(&mut self, slot: usize) -> bool {
+        let Some(preset) = self.presets.get_mut(slot) else {
+            return false;
+        };
+        *preset = Some(CameraPreset {
+            eye: self.camera.get_eye(),
+            target: self.camera.get_target(),
+            up: self.camera.get_up(),
+            fovy: self.camera.get_fovy(),
+        });
+        self.presets_saved += 1;
+        self.preset_saved_at[slot] = self.presets_saved;
+        true
+    }
+
+    /// Save the current viewpoint into the first free slot
+    /// Once every slot is used the oldest preset is overwritten, returns the slot and whether it replaced one
+    pub fn save_next_preset(&mut self) -> (usize, bool) {
+        let (slot, replaced) = match self.first_free_preset_slot() {
+            Some(slot) => (slot, false),
+            None => {
+                let oldest = (0..CAMERA_PRESET_SLOTS).min_by_key(|slot| self.preset_saved_at[*slot]).unwrap_or(0);
+                (oldest, true)
+            }
+        };
+        self.save_preset(slot);
+        (slot, replaced)
+    }
+
+    /// Jump to the viewpoint saved in `slot`, returns false if nothing is saved there
+    pub fn load_preset(&mut self, slot: usize, queue: &wgpu::Queue) -> bool {
+        let Some(Some(preset)) = self.presets.get(slot).copied() else {
+            return false;
+        };
+        self.camera.set_up(preset.up);
+        self.camera.set_fovy(preset.fovy);
+        self.look_at(preset.eye, preset.target, queue);
+        // The controller rebuilds up from its roll every frame, so roll to match or the saved up is lost
+        self.camera_controller.roll_towards(preset.up);
+        self.current_preset = Some(slot);
+        true
+    }
+
+    /// Load the next saved preset after the current one, wrapping around
+    /// Returns the slot that was loaded, or None if no presets are saved
+    pub fn cycle_preset(&mut self, queue: &wgpu::Queue) -> Option<usize> {
+        let start = self.current_preset.map_or(0, |slot| slot + 1);
+        let slot = (0..CAMERA_PRESET_SLOTS)
+            .map(|offset| (start + offset) % CAMERA_PRESET_SLOTS)
+            .find(|slot| self.presets[*slot].is_some())?;
+        self.load_preset(slot, queue);
+        Some(slot)
+    }
+
+    /// First slot with no preset saved in it
+    pub fn first_free_preset_slot(&self) -> Option<usize> {
+        self.presets.iter().position(Option::is_none)
     }
 
     /// Reset camera to default position and update GPU buffer
     pub fn reset(&mut self, queue: &wgpu::Queue) {
-        #[cfg(target_arch = "wasm32")]
-        console::log_1(&"RESETTING CAMERA".into());
+        self.camera.debug_log(|| "RESETTING CAMERA".to_string());
         
         self.camera.reset();
-        self.camera_uniform.update_view_proj(&self.camera);
-        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        self.camera_controller.look_at(self.camera.get_eye(), self.camera.get_target());
+        self.write_uniform(queue);
     }
 
     /// Get reference to camera bind group layout for pipeline creation
@@ -442,4 +1267,170 @@ impl CameraSystem {
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
-} 
\ No newline at end of file
+    pub scale: cgmath::Vector3<f32>, // Size along each axis relative to the model
+    pub alpha: f32, // 1.0 is opaque, anything lower is drawn with blending
+    pub color: [f32; 3], // Tint multiplied with the texture
+    pub handle: Option<RigidBodyHandle>, // Body this instance was built from, used for picking
+}
+
+impl Instance {
+    /// An opaque, untinted instance at the model's own size
+    pub fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            alpha: 1.0,
+            color: [1.0, 1.0, 1.0],
+            handle: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_projection_is_finite_for_reasonable_sizes() {
+        let sizes = [(800, 600), (1, 1), (1, 4000), (4000, 1), (0, 600), (800, 0), (0, 0)];
+        for (width, height) in sizes {
+            let mut camera = Camera::new();
+            camera.update_aspect(width, height);
+            let matrix = camera.build_view_projection_matrix();
+            assert!(is_matrix_finite(&matrix), "non-finite matrix for {}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn view_projection_is_projection_times_view() {
+        let mut camera = Camera::new();
+        camera.set_eye(cgmath::Point3::new(3.0, 4.0, -5.0));
+        camera.set_target(cgmath::Point3::new(0.5, 0.0, 1.0));
+        camera.update_aspect(1280, 720);
+
+        let combined = camera.build_view_projection_matrix();
+        let product = camera.projection_matrix() * camera.view_matrix();
+        let combined: [[f32; 4]; 4] = combined.into();
+        let product: [[f32; 4]; 4] = product.into();
+        for (a, b) in combined.iter().flatten().zip(product.iter().flatten()) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn view_matrix_puts_target_in_front_of_camera() {
+        let mut camera = Camera::new();
+        camera.set_eye(cgmath::Point3::new(0.0, 0.0, 2.0));
+        camera.set_target(cgmath::Point3::new(0.0, 0.0, 0.0));
+        camera.set_up(cgmath::Vector3::unit_y());
+
+        // Right-handed view space looks down -z, so the origin sits 2 units ahead
+        let origin = camera.view_matrix() * cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let expected = cgmath::Vector4::new(0.0, 0.0, -2.0, 1.0);
+        assert!((origin - expected).magnitude() < 1e-6, "origin maps to {:?}", origin);
+    }
+
+    #[test]
+    fn view_projection_includes_opengl_to_wgpu() {
+        let mut camera = Camera::new();
+        camera.set_eye(cgmath::Point3::new(0.0, 0.0, 2.0));
+        camera.set_target(cgmath::Point3::new(0.0, 0.0, 0.0));
+        camera.update_aspect(800, 600);
+
+        let proj = cgmath::perspective(cgmath::Deg(camera.fovy), camera.aspect, camera.znear, camera.zfar);
+        let expected: [[f32; 4]; 4] = (OPENGL_TO_WGPU_MATRIX * proj * camera.view_matrix()).into();
+        let combined: [[f32; 4]; 4] = camera.build_view_projection_matrix().into();
+        for (a, b) in combined.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn target_projects_to_viewport_center() {
+        let mut camera = Camera::new();
+        camera.set_eye(cgmath::Point3::new(4.0, 3.0, 6.0));
+        camera.set_target(cgmath::Point3::new(1.0, 0.5, -2.0));
+        camera.update_aspect(1280, 720);
+
+        let (x, y) = camera.world_to_screen(camera.get_target(), (1280, 720)).expect("target is in front of the camera");
+        assert!((x - 640.0).abs() < 0.01 && (y - 360.0).abs() < 0.01, "target at ({}, {})", x, y);
+
+        // Directly behind the eye
+        let behind = camera.get_eye() + (camera.get_eye() - camera.get_target());
+        assert_eq!(camera.world_to_screen(behind, (1280, 720)), None);
+    }
+
+    #[test]
+    fn zero_area_keeps_previous_aspect() {
+        let mut camera = Camera::new();
+        camera.update_aspect(1600, 800);
+        camera.update_aspect(0, 800);
+        camera.update_aspect(1600, 0);
+        assert_eq!(camera.aspect, 2.0);
+    }
+
+    #[test]
+    fn degenerate_camera_is_not_uploaded() {
+        let mut camera = Camera::new();
+        let mut uniform = CameraUniform::new();
+        assert!(uniform.update_view_proj(&camera));
+        let good = uniform.view_proj;
+
+        // Eye and target in the same place gives look_at a zero-length direction
+        camera.set_target(camera.get_eye());
+        assert!(!uniform.update_view_proj(&camera));
+        assert_eq!(uniform.view_proj, good);
+    }
+
+    #[test]
+    fn saved_camera_round_trips_through_json() {
+        let saved = SavedCamera {
+            eye: [-6.0, 37.5, 1e-3],
+            target: [0.25, 0.0, -2.0],
+            up: [0.0, 1.0, 0.0],
+            fovy: 45.0,
+            yaw: -123.456,
+            pitch: -60.0,
+        };
+        assert_eq!(SavedCamera::from_json(&saved.to_json()).unwrap(), saved);
+    }
+
+    #[test]
+    fn malformed_saved_camera_is_rejected() {
+        let valid = r#"{"eye": [0, 5, 10], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": 45, "yaw": -90, "pitch": -26}"#;
+        assert!(SavedCamera::from_json(valid).is_ok());
+        for json in [
+            "",
+            "not json",
+            r#"{"eye": [0, 5, 10], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": 45, "yaw": -90}"#, // Missing pitch
+            r#"{"eye": [0, 5], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": 45, "yaw": -90, "pitch": 0}"#,
+            r#"{"eye": [0, 5, 10], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": "wide", "yaw": -90, "pitch": 0}"#,
+            r#"{"eye": [0, 5, 10], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": NaN, "yaw": -90, "pitch": 0}"#,
+            r#"{"eye": [1, 1, 1], "target": [1, 1, 1], "up": [0, 1, 0], "fovy": 45, "yaw": -90, "pitch": 0}"#,
+            r#"{"eye": [0, 5, 10] "target": [0, 0, 0]}"#,
+        ] {
+            assert!(SavedCamera::from_json(json).is_err(), "accepted {:?}", json);
+        }
+    }
+
+    #[test]
+    fn roll_towards_keeps_a_tilted_up_through_updates() {
+        let eye = cgmath::Point3::new(1.0, 2.0, 5.0);
+        let target = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let mut controller = CameraController::default();
+        controller.look_at(eye, target);
+
+        // Tilt a level up vector around the view direction, as a rolled preset would have saved it
+        let forward = (target - eye).normalize();
+        let level_up = forward.cross(cgmath::Vector3::unit_y()).cross(forward).normalize();
+        let tilted = cgmath::Quaternion::from_axis_angle(forward, cgmath::Deg(-30.0)).rotate_vector(level_up);
+        controller.roll_towards(tilted);
+
+        let mut camera = Camera::new();
+        camera.set_eye(eye);
+        camera.set_target(target);
+        controller.update_camera(&mut camera, 1.0 / 60.0);
+        assert!((camera.get_up() - tilted).magnitude() < 1e-4, "up is {:?}, expected {:?}", camera.get_up(), tilted);
+    }
+}
@@ -0,0 +1,57 @@
+// Hue step between consecutive colors, the golden ratio conjugate keeps neighbours far apart on the wheel
+const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034;
+
+/// A stable, distinct color for the `i`th body, the same every run
+/// Hues step around the wheel by the golden ratio so no two nearby indices look alike
+pub fn color_for_index(i: usize) -> [f32; 3] {
+    let hue = (i as f32 * GOLDEN_RATIO_CONJUGATE).fract();
+    hsv_to_rgb(hue, 0.5, 0.95)
+}
+
+/// Convert hue, saturation and value (all 0 to 1) to RGB
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = value - c;
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_colors_are_stable_and_distinct() {
+        let colors: Vec<[f32; 3]> = (0..8).map(color_for_index).collect();
+
+        // Index 0 is hue 0, i.e. a light red
+        let [r, g, b] = colors[0];
+        assert!((r - 0.95).abs() < 1e-5);
+        assert!((g - 0.475).abs() < 1e-5);
+        assert!((b - 0.475).abs() < 1e-5);
+
+        for (i, a) in colors.iter().enumerate() {
+            assert_eq!(*a, color_for_index(i));
+            for b in &colors[i + 1..] {
+                let distance: f32 = a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum();
+                assert!(distance > 0.05, "{:?} and {:?} are too similar", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn hsv_primaries() {
+        assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), [1.0, 0.0, 0.0]);
+        assert_eq!(hsv_to_rgb(1.0 / 3.0, 1.0, 1.0), [0.0, 1.0, 0.0]);
+        assert_eq!(hsv_to_rgb(2.0 / 3.0, 1.0, 1.0), [0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,333 @@
+use cgmath::{InnerSpace, Quaternion, Rotation, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::model::ModelVertex;
+use crate::physics::BodyShape;
+use crate::renderer::ColorSpace;
+
+// Segments used to approximate each circle of a ball outline
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// A colored point, used for debug lines and overlay triangles
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl DebugVertex {
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self { position, color }
+    }
+
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Line Buffer"),
+        size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Colored lines drawn on top of the scene, rebuilt on the CPU whenever they change
+/// Add lines, then `upload` them before the frame is rendered
+pub struct DebugLines {
+    pipeline: wgpu::RenderPipeline,
+    vertices: Vec<DebugVertex>,
+    buffer: wgpu::Buffer,
+    capacity: usize, // How many vertices buffer can hold
+    uploaded: usize, // How many vertices the last upload wrote
+}
+
+impl DebugLines {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[DebugVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &[("apply_gamma", if color_space == ColorSpace::Linear { 1.0 } else { 0.0 })],
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList, // every two vertices are one line
+                ..Default::default()
+            },
+            // Lines sit exactly on the surfaces they outline, so draw them over everything instead of z-fighting
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let capacity = 256;
+        Self {
+            pipeline,
+            vertices: Vec::new(),
+            buffer: create_vertex_buffer(device, capacity),
+            capacity,
+            uploaded: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, start: Vector3<f32>, end: Vector3<f32>, color: [f32; 3]) {
+        self.vertices.push(DebugVertex { position: start.into(), color });
+        self.vertices.push(DebugVertex { position: end.into(), color });
+    }
+
+    /// The 12 edges of a box
+    pub fn cuboid(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, half_extents: Vector3<f32>, color: [f32; 3]) {
+        let corner = |x: f32, y: f32, z: f32| {
+            position + rotation.rotate_vector(Vector3::new(x * half_extents.x, y * half_extents.y, z * half_extents.z))
+        };
+        let corners = [
+            corner(-1.0, -1.0, -1.0),
+            corner(1.0, -1.0, -1.0),
+            corner(1.0, -1.0, 1.0),
+            corner(-1.0, -1.0, 1.0),
+            corner(-1.0, 1.0, -1.0),
+            corner(1.0, 1.0, -1.0),
+            corner(1.0, 1.0, 1.0),
+            corner(-1.0, 1.0, 1.0),
+        ];
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(corners[i], corners[next], color); // bottom
+            self.line(corners[i + 4], corners[next + 4], color); // top
+            self.line(corners[i], corners[i + 4], color); // sides
+        }
+    }
+
+    /// Three circles, one around each axis
+    pub fn ball(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, radius: f32, color: [f32; 3]) {
+        let axes = [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x()),
+        ];
+        for (u, v) in axes {
+            let point = |i: usize| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                position + rotation.rotate_vector((u * angle.cos() + v * angle.sin()) * radius)
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    pub fn shape(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, shape: BodyShape, color: [f32; 3]) {
+        match shape {
+            BodyShape::Cuboid { half_extents } => self.cuboid(position, rotation, half_extents, color),
+            BodyShape::Ball { radius } => self.ball(position, rotation, radius, color),
+        }
+    }
+
+    /// A line of `length` from each vertex along its normal, for a mesh placed with `position`, `rotation` and `scale`
+    /// Colored by direction (x red, y green, z blue, negative axes darker), so flipped normals stand out
+    pub fn normals(
+        &mut self,
+        vertices: &[ModelVertex],
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+        length: f32,
+    ) {
+        for vertex in vertices {
+            let local = Vector3::from(vertex.position);
+            let start = position + rotation.rotate_vector(Vector3::new(local.x * scale.x, local.y * scale.y, local.z * scale.z));
+            // Normals scale by the inverse, or a stretched box would tilt them
+            let normal = Vector3::from(vertex.normal);
+            let normal = rotation.rotate_vector(Vector3::new(normal.x / scale.x, normal.y / scale.y, normal.z / scale.z));
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+            let color = [normal.x * 0.5 + 0.5, normal.y * 0.5 + 0.5, normal.z * 0.5 + 0.5];
+            self.line(start, start + normal * length, color);
+        }
+    }
+
+    /// Copy the lines to the GPU, growing the buffer if they don't fit
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            let mut capacity = self.capacity.max(1);
+            while capacity < self.vertices.len() {
+                capacity *= 2;
+            }
+            self.buffer = create_vertex_buffer(device, capacity);
+            self.capacity = capacity;
+        }
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.uploaded = self.vertices.len();
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.uploaded == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.uploaded as u32, 0..1);
+    }
+}
+
+/// Flat colored triangles drawn after the scene without any depth testing, so they're always on top
+/// The generic piece behind gizmos, selection outlines and HUD shapes
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: Option<wgpu::Buffer>,
+    index_buffer: Option<wgpu::Buffer>,
+    index_count: u32,
+}
+
+impl Overlay {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[DebugVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &[("apply_gamma", if color_space == ColorSpace::Linear { 1.0 } else { 0.0 })],
+                    ..Default::default()
+                },
+            }),
+            // Not culled, so winding doesn't matter for 2D shapes
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn in its own pass without a depth attachment
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            vertex_buffer: None,
+            index_buffer: None,
+            index_count: 0,
+        }
+    }
+
+    /// Replace the triangles, every three `indices` into `vertices` make one
+    pub fn set(&mut self, device: &wgpu::Device, vertices: &[DebugVertex], indices: &[u32]) {
+        if vertices.is_empty() || indices.is_empty() {
+            self.clear();
+            return;
+        }
+        self.vertex_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        }));
+        self.index_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        }));
+        self.index_count = indices.len() as u32;
+    }
+
+    pub fn clear(&mut self) {
+        self.vertex_buffer = None;
+        self.index_buffer = None;
+        self.index_count = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_count == 0
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        let (Some(vertex_buffer), Some(index_buffer)) = (&self.vertex_buffer, &self.index_buffer) else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+}
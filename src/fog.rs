@@ -0,0 +1,104 @@
+use wgpu::util::DeviceExt;
+
+// Matches `Fog` in shader.wgsl, a vec3 is 16-byte aligned so each one is followed by a float
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FogUniform {
+    color: [f32; 3],
+    density: f32,
+    eye: [f32; 3], // Distances are measured from here
+    _padding: f32,
+}
+
+/// Exponential distance fog, the fragment shader fades surfaces towards `color` the farther they are from the camera
+/// Off (density 0) until `set` is called
+pub struct Fog {
+    color: [f32; 3],
+    density: f32,
+    eye: [f32; 3],
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Fog {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let color = [0.1, 0.2, 0.3];
+        let uniform = FogUniform { color, density: 0.0, eye: [0.0; 3], _padding: 0.0 };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("fog_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("fog_bind_group"),
+        });
+
+        Self {
+            color,
+            density: 0.0,
+            eye: [0.0; 3],
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Fog `color` (linear RGB) and how quickly it thickens per unit of distance, 0 turns it off
+    /// Half the light gets through at a distance of ln(2) / density, e.g. about 35 units at 0.02
+    pub fn set(&mut self, color: [f32; 3], density: f32, queue: &wgpu::Queue) {
+        self.color = color;
+        self.density = density.max(0.0);
+        self.write_uniform(queue);
+    }
+
+    /// Move the point distances are measured from, call when the camera moves
+    pub fn set_eye(&mut self, eye: cgmath::Point3<f32>, queue: &wgpu::Queue) {
+        let eye = eye.into();
+        if eye != self.eye {
+            self.eye = eye;
+            self.write_uniform(queue);
+        }
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = FogUniform { color: self.color, density: self.density, eye: self.eye, _padding: 0.0 };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
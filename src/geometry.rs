@@ -1,54 +1,46 @@
-use bytemuck;
-use wgpu;
+use crate::model::ModelVertex;
 
-#[repr(C)] //layout the struct in memory how a C compiler would ->
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Vertex {
-    position: [f32; 3], //syntax for making arrays of type f32 with a compile length of 3
-    color: [f32; 3],
-    tex_coords: [f32; 2], // texture coordinates
+/// A flat square with sides of `size` in the XZ plane, centered on the origin and facing +y
+/// Texture coordinates span 0..1 across the square
+pub fn plane(size: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let half = size / 2.0;
+    let normal = [0.0, 1.0, 0.0];
+    let vertices = vec![
+        ModelVertex { position: [-half, 0.0, -half], tex_coords: [0.0, 0.0], normal },
+        ModelVertex { position: [-half, 0.0, half], tex_coords: [0.0, 1.0], normal },
+        ModelVertex { position: [half, 0.0, half], tex_coords: [1.0, 1.0], normal },
+        ModelVertex { position: [half, 0.0, -half], tex_coords: [1.0, 0.0], normal },
+    ];
+    // Counter-clockwise seen from above, so back-face culling keeps the top
+    let indices = vec![0, 1, 2, 0, 2, 3];
+    (vertices, indices)
 }
 
-impl Vertex {
-    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress, //array_stride says how big the vertex is. When the shader goes to read the next vertex, it will skip over the array_stride number of bytes
-            step_mode: wgpu::VertexStepMode::Vertex, //here for stepmode it defines that each element in array represents pre-vertex data and not pre-instance 
-            //these attributes usually correspond to the member variables in the Vertex struct
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0, //for the first attribute, offset is zero, and later attributes will have sum of size_of of the previous' data
-                    shader_location: 0, //saying that this attribute corresponds to location of 0 in shaders -> telling the gpu how position and color are mapped in shaders
-                    format: wgpu::VertexFormat::Float32x3, // 6.tells the shader the shape of the attribute. Float32x3 corresponds to vec3<f32> in shader code. 
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress + std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x2
-                }
-            ]
+/// A cube with sides of `size` centered on the origin, with flat per-face normals
+/// Faces are counter-clockwise from outside and each one maps the whole texture
+pub fn cube(size: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let half = size / 2.0;
+    // (normal, u, v) per face, with u x v = normal so corners in u/v order wind counter-clockwise
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    // Texture v runs down the image, so the top of each face samples v = 0
+    let corners = [(-1.0, -1.0, [0.0, 1.0]), (1.0, -1.0, [1.0, 1.0]), (1.0, 1.0, [1.0, 0.0]), (-1.0, 1.0, [0.0, 0.0])];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let base = vertices.len() as u32;
+        for (su, sv, tex_coords) in corners {
+            let position = [0, 1, 2].map(|i| (normal[i] + u[i] * su + v[i] * sv) * half);
+            vertices.push(ModelVertex { position, tex_coords, normal });
         }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
     }
+    (vertices, indices)
 }
-
-//data that make up the triangle
-//vertex data laid out in ccw order bc earlier we talked about having the front_face to be ccw -> with this data we have the triangle facing us
-pub const VERTICES: &[Vertex] = &[
-    Vertex { position: [-0.0868241, 0.49240386, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.4131759, 0.00759614] }, // A
-    Vertex { position: [-0.49513406, 0.06958647, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.0048659444, 0.43041354] }, // B
-    Vertex { position: [-0.21918549, -0.44939706, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.28081453, 0.949397] }, // C
-    Vertex { position: [0.35966998, -0.3473291, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.85967, 0.84732914] }, // D
-    Vertex { position: [0.44147372, 0.2347359, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.9414737, 0.2652641] }, // E
-];
-
-//using index buffers to save on memory -> save us having to keep track of duplicate data. You only need to map the order that the vertices appear in. 
-pub const INDICES: &[u16] = &[
-    0, 1, 4,
-    1, 2, 4,
-    2, 3, 4,
-]; 
\ No newline at end of file
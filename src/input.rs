@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use winit::keyboard::KeyCode;
+
+/// Things the user can do from the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    Sprint, // Held to move the camera faster
+    RollLeft,
+    RollRight,
+    Reset,
+    Jump,
+    Shoot, // Launch a cube from the camera
+    Explode, // Blast bodies away from where the camera is looking
+    Freeze,   // Heavily damp every body so motion dies down
+    Unfreeze, // Remove damping again
+    SavePreset,
+    CyclePreset,
+    ToggleColliders,
+    ToggleCulling,
+    ToggleLabels,
+    ToggleNormals, // Lines along every vertex normal of the bodies
+    ToggleStats, // Frame rate readout in the corner of the window
+    ToggleTopDown, // Orthographic view straight down at the scene
+    LoadScene(usize), // Index into Scene::ALL
+    Exit,
+}
+
+/// Maps actions to the keys that trigger them
+/// An action can have several keys (e.g. W and the up arrow both move forward)
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: HashMap<Action, Vec<KeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = HashMap::new();
+        bindings.insert(Action::Forward, vec![KeyCode::KeyW, KeyCode::ArrowUp]);
+        bindings.insert(Action::Back, vec![KeyCode::KeyS, KeyCode::ArrowDown]);
+        bindings.insert(Action::Left, vec![KeyCode::KeyA, KeyCode::ArrowLeft]);
+        bindings.insert(Action::Right, vec![KeyCode::KeyD, KeyCode::ArrowRight]);
+        bindings.insert(Action::Up, vec![KeyCode::KeyE]);
+        bindings.insert(Action::Down, vec![KeyCode::KeyQ]);
+        bindings.insert(Action::Sprint, vec![KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        bindings.insert(Action::RollLeft, vec![KeyCode::KeyZ]);
+        bindings.insert(Action::RollRight, vec![KeyCode::KeyX]);
+        bindings.insert(Action::Reset, vec![KeyCode::KeyR]);
+        bindings.insert(Action::Jump, vec![KeyCode::Space]);
+        bindings.insert(Action::Shoot, vec![KeyCode::KeyC]);
+        bindings.insert(Action::Explode, vec![KeyCode::KeyB]);
+        bindings.insert(Action::Freeze, vec![KeyCode::KeyG]);
+        bindings.insert(Action::Unfreeze, vec![KeyCode::KeyH]);
+        bindings.insert(Action::SavePreset, vec![KeyCode::KeyP]);
+        bindings.insert(Action::CyclePreset, vec![KeyCode::Tab]);
+        bindings.insert(Action::ToggleColliders, vec![KeyCode::F1]);
+        bindings.insert(Action::ToggleCulling, vec![KeyCode::F2]);
+        bindings.insert(Action::ToggleLabels, vec![KeyCode::F3]);
+        bindings.insert(Action::ToggleNormals, vec![KeyCode::F4]);
+        bindings.insert(Action::ToggleStats, vec![KeyCode::F5]);
+        bindings.insert(Action::ToggleTopDown, vec![KeyCode::KeyT]);
+        bindings.insert(Action::LoadScene(0), vec![KeyCode::Digit1]);
+        bindings.insert(Action::LoadScene(1), vec![KeyCode::Digit2]);
+        bindings.insert(Action::LoadScene(2), vec![KeyCode::Digit3]);
+        bindings.insert(Action::Exit, vec![KeyCode::Escape]);
+
+        Self { bindings }
+    }
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the keys bound to `action`
+    pub fn bind(&mut self, action: Action, keys: &[KeyCode]) {
+        // A key should only trigger one action, so take it away from whatever had it before
+        for bound in self.bindings.values_mut() {
+            bound.retain(|key| !keys.contains(key));
+        }
+        self.bindings.insert(action, keys.to_vec());
+    }
+
+    /// Keys currently bound to `action`
+    pub fn keys_for(&self, action: Action) -> &[KeyCode] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The action `key` triggers, if any
+    pub fn action_for(&self, key: KeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+}
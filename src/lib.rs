@@ -1,11 +1,22 @@
-mod camera;
-mod geometry;
-mod renderer;
+pub mod camera;
+pub mod geometry;
+pub mod renderer;
 mod app;
-mod texture;
-mod model;
-mod resources;
-mod physics;
+mod color;
+mod debug;
+mod fog;
+mod input;
+mod light;
+pub mod texture;
+mod timing;
+pub mod model;
+mod particles;
+mod picking;
+pub mod resources;
+mod scene;
+mod sim;
+mod text;
+pub mod physics;
 
 
 use winit::event_loop::EventLoop;
@@ -14,7 +25,29 @@ use winit::event_loop::EventLoop;
 use wasm_bindgen::prelude::*;
 
 pub use app::App;
-pub use renderer::State;
+pub use camera::Projection;
+pub use color::{color_for_index, hsv_to_rgb};
+pub use debug::DebugVertex;
+pub use input::{Action, KeyBindings};
+pub use light::{Light, MAX_LIGHTS};
+pub use physics::{BodyShape, BodySummary, Collision, Command, ContactImpulse, CubeOptions, Ground, PhysicsBody, PhysicsWorld, ShapeKind, StaticCollider};
+pub use renderer::{Checkerboard, ColorSpace, PresentMode, RenderError, Renderer, State, StateBuilder};
+pub use picking::PickResult;
+pub use scene::Scene;
+pub use sim::{SimLoop, SimTick};
+
+/// The types most programs need, `use physicsrenderer::prelude::*;` to get them all at once
+pub mod prelude {
+    pub use crate::app::App;
+    pub use crate::camera::{Camera, CameraController, CameraSystem, Projection};
+    pub use crate::input::{Action, KeyBindings};
+    pub use crate::light::Light;
+    pub use crate::model::Model;
+    pub use crate::physics::{PhysicsBody, PhysicsWorld};
+    pub use crate::renderer::{Renderer, State, StateBuilder};
+    pub use crate::scene::Scene;
+    pub use crate::texture::Texture;
+}
 
 pub fn run() -> anyhow::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
@@ -0,0 +1,137 @@
+use wgpu::util::DeviceExt;
+
+/// Most point lights the shader accepts, the uniform is sized for exactly this many
+pub const MAX_LIGHTS: usize = 8;
+
+/// A point light, its brightness falls off with distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub position: cgmath::Point3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn new(position: cgmath::Point3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self { position, color, intensity }
+    }
+}
+
+// Matches `Light` in shader.wgsl, a vec3 is 16-byte aligned so each one is followed by a float
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    lights: [LightRaw; MAX_LIGHTS],
+    count: u32,
+    _padding: [u32; 3], // Uniforms are sized in multiples of 16 bytes
+}
+
+impl LightUniform {
+    fn from_lights(lights: &[Light]) -> Self {
+        let mut uniform = Self::zeroed();
+        for (raw, light) in uniform.lights.iter_mut().zip(lights) {
+            *raw = LightRaw {
+                position: light.position.into(),
+                intensity: light.intensity,
+                color: light.color,
+                _padding: 0.0,
+            };
+        }
+        uniform.count = lights.len().min(MAX_LIGHTS) as u32;
+        uniform
+    }
+
+    fn zeroed() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+}
+
+/// The scene's point lights and the uniform buffer the fragment shader reads them from
+pub struct LightSystem {
+    lights: Vec<Light>,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl LightSystem {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("light_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("light_bind_group"),
+        });
+
+        Self {
+            lights: Vec::new(),
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Add a light, returns false (and ignores it) if there are already MAX_LIGHTS
+    pub fn add_light(&mut self, light: Light, queue: &wgpu::Queue) -> bool {
+        if self.lights.len() >= MAX_LIGHTS {
+            return false;
+        }
+        self.lights.push(light);
+        self.write_uniform(queue);
+        true
+    }
+
+    /// Remove every light, the scene goes back to unlit
+    pub fn clear_lights(&mut self, queue: &wgpu::Queue) {
+        self.lights.clear();
+        self.write_uniform(queue);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = LightUniform::from_lights(&self.lights);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
@@ -1,10 +1,24 @@
-use physicsrenderer::App;
-
 fn main() -> anyhow::Result<()> {
     println!("Physics Renderer");
     println!("Controls:");
     println!("  WASD - Move camera");
+    println!("  Q/E - Move camera down/up");
+    println!("  Shift - Hold to move faster");
+    println!("  Z/X - Roll camera left/right");
     println!("  R - Reset camera to default");
+    println!("  G/H - Damp all motion / remove damping");
+    println!("  P - Save camera preset, Tab - Cycle presets");
+    println!("  1/2/3 - Load the bouncy cubes, dominoes or pyramid scene");
+    println!("  Left click - Drop a cube");
+    println!("  C - Shoot a cube");
+    println!("  B - Explosion where you're looking");
+    println!("  Right drag - Grab a body and fling it");
+    println!("  F1 - Toggle collider outlines");
+    println!("  F2 - Toggle backface culling");
+    println!("  F3 - Toggle body labels");
+    println!("  F4 - Toggle vertex normals");
+    println!("  F5 - Toggle the frame rate readout");
+    println!("  T - Toggle top-down view");
     println!("  Escape - Exit");
     println!();
     
@@ -1,5 +1,8 @@
 use std::ops::Range;
 
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
 use crate::texture::Texture;
 
 pub trait Vertex {
@@ -47,12 +50,63 @@ pub struct Material {
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    /// A material that samples `texture` everywhere, bound with the model pipeline's texture layout
+    pub fn from_texture(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, name: &str, texture: Texture) -> Self {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some(name),
+        });
+        Self {
+            name: name.to_string(),
+            diffuse_texture: Some(texture),
+            bind_group,
+        }
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat, // Uint16 when every index fits, see create_index_buffer
     pub num_elements: u32,
     pub material: usize,
+    // CPU-side copies of the uploaded data, kept around for physics and mesh processing
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// Upload geometry built in code, e.g. from `geometry::plane`
+    pub fn from_data(device: &wgpu::Device, name: &str, vertices: Vec<ModelVertex>, indices: Vec<u32>, material: usize) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let (index_buffer, index_format) = create_index_buffer(device, name, &indices);
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format,
+            num_elements: indices.len() as u32,
+            material,
+            vertices,
+            indices,
+        }
+    }
 }
 
 pub struct Model {
@@ -60,6 +114,88 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// Collect the positions and triangles of every mesh into a single list
+    /// This is the shape `PhysicsWorld::add_trimesh_static` expects, so a loaded OBJ can be used as collision geometry
+    pub fn collision_geometry(&self) -> (Vec<Point3<f32>>, Vec<[u32; 3]>) {
+        let mut positions = Vec::new();
+        let mut triangles = Vec::new();
+
+        for mesh in &self.meshes {
+            // Indices are local to each mesh, so shift them past the vertices already collected
+            let offset = positions.len() as u32;
+            positions.extend(mesh.vertices.iter().map(|v| Point3::new(v.position[0], v.position[1], v.position[2])));
+            triangles.extend(
+                mesh.indices
+                    .chunks_exact(3)
+                    .map(|tri| [tri[0] + offset, tri[1] + offset, tri[2] + offset]),
+            );
+        }
+
+        (positions, triangles)
+    }
+
+    /// Replace every mesh's vertex normals with ones computed from its triangles and re-upload them
+    /// For models whose source file didn't include normals
+    pub fn compute_normals(&mut self, queue: &wgpu::Queue) {
+        for mesh in &mut self.meshes {
+            compute_vertex_normals(&mut mesh.vertices, &mesh.indices);
+            queue.write_buffer(&mesh.vertex_buffer, 0, bytemuck::cast_slice(&mesh.vertices));
+        }
+    }
+}
+
+/// The smallest index format that can hold every index, Uint16 unless the mesh has more than 65536 vertices
+pub fn index_format_for(indices: &[u32]) -> wgpu::IndexFormat {
+    if indices.iter().all(|index| *index <= u16::MAX as u32) {
+        wgpu::IndexFormat::Uint16
+    } else {
+        wgpu::IndexFormat::Uint32
+    }
+}
+
+/// Upload `indices` in the format `index_format_for` picks, halving the buffer for all but huge meshes
+pub fn create_index_buffer(device: &wgpu::Device, name: &str, indices: &[u32]) -> (wgpu::Buffer, wgpu::IndexFormat) {
+    let format = index_format_for(indices);
+    let narrow;
+    let contents = match format {
+        wgpu::IndexFormat::Uint16 => {
+            narrow = indices.iter().map(|index| *index as u16).collect::<Vec<_>>();
+            bytemuck::cast_slice(&narrow)
+        }
+        wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(indices),
+    };
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents,
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    (buffer, format)
+}
+
+/// Smooth normals from counter-clockwise triangles: each vertex gets the normalized sum of the
+/// face normals around it, weighted by face area since the cross products aren't normalized first
+pub fn compute_vertex_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (Some(va), Some(vb), Some(vc)) = (vertices.get(a), vertices.get(b), vertices.get(c)) else {
+            continue;
+        };
+        let pa = Vector3::from(va.position);
+        let face_normal = (Vector3::from(vb.position) - pa).cross(Vector3::from(vc.position) - pa);
+        normals[a] += face_normal;
+        normals[b] += face_normal;
+        normals[c] += face_normal;
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        // Vertices not used by any triangle keep a harmless up normal
+        vertex.normal = if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0, 1.0, 0.0] };
+    }
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(&mut self, mesh: &'a Mesh, material: &'a Material, camera_bind_group: &'a wgpu::BindGroup);
     fn draw_mesh_instanced(
@@ -76,6 +212,13 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
     );
+    /// Draw just the geometry without binding materials, for passes that don't sample textures
+    fn draw_model_geometry_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -94,7 +237,7 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(1, &material.bind_group, &[]);
         self.set_bind_group(0, camera_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
@@ -115,4 +258,62 @@ where
             self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
         }
     }
-} 
\ No newline at end of file
+
+    fn draw_model_geometry_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_bind_group(0, camera_bind_group, &[]);
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, tex_coords: [0.0, 0.0], normal: [0.0, 0.0, 0.0] }
+    }
+
+    #[test]
+    fn cube_normals_point_outward() {
+        // Unit cube centered on the origin with counter-clockwise faces seen from outside
+        let mut vertices = [
+            [-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [-0.5, 0.5, -0.5],
+            [-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, 0.5, 0.5],
+        ]
+        .map(vertex);
+        let indices = [
+            4, 5, 6, 4, 6, 7, // +z
+            1, 0, 3, 1, 3, 2, // -z
+            5, 1, 2, 5, 2, 6, // +x
+            0, 4, 7, 0, 7, 3, // -x
+            7, 6, 2, 7, 2, 3, // +y
+            0, 1, 5, 0, 5, 4, // -y
+        ];
+
+        compute_vertex_normals(&mut vertices, &indices);
+
+        for v in &vertices {
+            let position = Vector3::from(v.position);
+            let normal = Vector3::from(v.normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5, "normal {:?} isn't unit length", normal);
+            // Each corner's normal should lean the same way as the corner itself
+            assert!(normal.dot(position) > 0.0, "normal {:?} at {:?} points inward", normal, position);
+        }
+    }
+
+    #[test]
+    fn index_format_widens_past_u16() {
+        assert_eq!(index_format_for(&[]), wgpu::IndexFormat::Uint16);
+        assert_eq!(index_format_for(&[0, 1, 65535]), wgpu::IndexFormat::Uint16);
+        assert_eq!(index_format_for(&[0, 65536, 1]), wgpu::IndexFormat::Uint32);
+    }
+}
@@ -0,0 +1,282 @@
+use cgmath::Vector3;
+
+use crate::renderer::ColorSpace;
+
+// Sparks alive at once, new bursts overwrite the oldest ones
+const MAX_PARTICLES: usize = 4096;
+// Matches @workgroup_size in particles.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+// Sparks for the gentlest and the hardest bursts
+const MIN_SPARKS_PER_BURST: usize = 4;
+const MAX_SPARKS_PER_BURST: usize = 48;
+// Sparks fall faster than the simulation's gentle gravity so they read as sparks
+const SPARK_GRAVITY: f32 = -9.81;
+
+// Matches `Particle` in particles.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    life: f32, // Seconds left, dead at 0
+    velocity: [f32; 3],
+    max_life: f32,
+}
+
+impl Particle {
+    // position, life and max_life, velocity is skipped since only the compute shader needs it
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = [
+        wgpu::VertexAttribute { offset: 0, shader_location: 0, format: wgpu::VertexFormat::Float32x3 },
+        wgpu::VertexAttribute { offset: 12, shader_location: 1, format: wgpu::VertexFormat::Float32 },
+        wgpu::VertexAttribute { offset: 28, shader_location: 2, format: wgpu::VertexFormat::Float32 },
+    ];
+
+    // The render pass reads the particle buffer directly, one particle per instance
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Sparks simulated by a compute shader and drawn as small additive quads
+/// Needs compute shader support, so it isn't available on WebGL
+pub struct ParticleSystem {
+    compute_pipeline: wgpu::ComputePipeline,
+    render_pipeline: wgpu::RenderPipeline,
+    particle_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    compute_bind_group: wgpu::BindGroup,
+    next: usize, // Slot the next spark is written to
+    time_left: f32, // Until the newest spark dies, nothing runs once it's zero
+    step_pending: bool, // update wrote new params that dispatch hasn't run yet
+    seed: u32, // xorshift state for spark directions
+}
+
+impl ParticleSystem {
+    /// Whether `adapter` can run the particle compute shader
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particles.wgsl").into()),
+        });
+
+        let particle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (MAX_PARTICLES * std::mem::size_of::<Particle>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false, // Zeroed, so every particle starts dead
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Params Buffer"),
+            size: 16, // dt and gravity, padded to 16 bytes
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let compute_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("particle_compute_bind_group_layout"),
+        });
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_compute_bind_group"),
+        });
+
+        let compute_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Compute Pipeline"),
+            layout: Some(&compute_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let render_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Render Pipeline"),
+            layout: Some(&render_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Particle::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Additive, so overlapping sparks glow brighter and order doesn't matter
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &[("apply_gamma", if color_space == ColorSpace::Linear { 1.0 } else { 0.0 })],
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Hidden behind bodies, but they don't hide each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            compute_pipeline,
+            render_pipeline,
+            particle_buffer,
+            params_buffer,
+            compute_bind_group,
+            next: 0,
+            time_left: 0.0,
+            step_pending: false,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    /// Throw sparks out from `position`, more of them the closer `intensity` is to 1
+    pub fn burst(&mut self, queue: &wgpu::Queue, position: Vector3<f32>, intensity: f32) {
+        let extra = (MAX_SPARKS_PER_BURST - MIN_SPARKS_PER_BURST) as f32 * intensity.clamp(0.0, 1.0);
+        let count = MIN_SPARKS_PER_BURST + extra as usize;
+        let sparks = (0..count)
+            .map(|_| {
+                // Random direction in the upper hemisphere
+                let direction = Vector3::new(self.random() * 2.0 - 1.0, self.random(), self.random() * 2.0 - 1.0);
+                let speed = 1.0 + self.random() * 2.0;
+                let life = 0.4 + self.random() * 0.4;
+                Particle {
+                    position: position.into(),
+                    life,
+                    velocity: (direction * speed).into(),
+                    max_life: life,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.time_left = sparks.iter().map(|spark| spark.life).fold(self.time_left, f32::max);
+
+        // The ring may wrap, in which case the burst is written in two pieces
+        let first = sparks.len().min(MAX_PARTICLES - self.next);
+        let stride = std::mem::size_of::<Particle>();
+        queue.write_buffer(&self.particle_buffer, (self.next * stride) as wgpu::BufferAddress, bytemuck::cast_slice(&sparks[..first]));
+        if first < sparks.len() {
+            queue.write_buffer(&self.particle_buffer, 0, bytemuck::cast_slice(&sparks[first..]));
+        }
+        self.next = (self.next + sparks.len()) % MAX_PARTICLES;
+    }
+
+    /// True while any spark might still be alive
+    pub fn is_active(&self) -> bool {
+        self.time_left > 0.0
+    }
+
+    /// Queue a step of `dt` seconds, run by the next `dispatch`
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        if !self.is_active() {
+            return;
+        }
+        self.time_left = (self.time_left - dt).max(0.0);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[dt, SPARK_GRAVITY, 0.0, 0.0]));
+        self.step_pending = true;
+    }
+
+    /// Record the compute pass that advances every spark, if `update` queued a step
+    pub fn dispatch(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.step_pending {
+            return;
+        }
+        self.step_pending = false;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Compute Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.dispatch_workgroups((MAX_PARTICLES as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if !self.is_active() {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.particle_buffer.slice(..));
+        render_pass.draw(0..6, 0..MAX_PARTICLES as u32);
+    }
+
+    // xorshift32, plenty for picking spark directions
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
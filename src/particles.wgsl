@@ -0,0 +1,104 @@
+// GPU sparks: cs_main moves them, vs_main/fs_main draw each one as a small glowing quad
+
+// Must match Particle in particles.rs
+struct Particle {
+    position: vec3<f32>,
+    life: f32, // Seconds left, dead at 0
+    velocity: vec3<f32>,
+    max_life: f32,
+}
+
+struct SimParams {
+    dt: f32,
+    gravity: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(1)
+var<uniform> params: SimParams;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= arrayLength(&particles) {
+        return;
+    }
+    var particle = particles[i];
+    if particle.life <= 0.0 {
+        return;
+    }
+    particle.velocity.y += params.gravity * params.dt;
+    particle.position += particle.velocity * params.dt;
+    particle.life -= params.dt;
+    particles[i] = particle;
+}
+
+// Render pass, the particle buffer is read as per-instance vertex data
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Set by the renderer when the surface isn't sRGB, so we have to gamma encode ourselves
+override apply_gamma: bool = false;
+
+// Half the width of a spark in clip space units, before the perspective divide shrinks far ones
+const SIZE: f32 = 0.05;
+
+struct ParticleInput {
+    @location(0) position: vec3<f32>,
+    @location(1) life: f32,
+    @location(2) max_life: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) fade: f32, // 1 when the spark is new, 0 when it dies
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    // Two triangles covering a square, picked by vertex index
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.fade = clamp(particle.life / max(particle.max_life, 0.0001), 0.0, 1.0);
+    if particle.life <= 0.0 {
+        // Past the far plane, so dead sparks are clipped away
+        out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+    let clip = camera.view_proj * vec4<f32>(particle.position, 1.0);
+    out.clip_position = clip + vec4<f32>(corner * SIZE, 0.0, 0.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Round sparks
+    let distance = length(in.corner);
+    if distance > 1.0 {
+        discard;
+    }
+    // Blended additively, so darker just means more transparent
+    let hot = vec3<f32>(1.0, 0.9, 0.5);
+    let cool = vec3<f32>(1.0, 0.3, 0.05);
+    var color = mix(cool, hot, in.fade) * in.fade * (1.0 - distance);
+    if apply_gamma {
+        color = pow(color, vec3<f32>(1.0 / 2.2));
+    }
+    return vec4<f32>(color, 1.0);
+}
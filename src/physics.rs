@@ -1,6 +1,9 @@
 use rapier3d::prelude::*;
-use cgmath::{Vector3, Quaternion, Deg, Zero, Rotation3};
-use std::collections::HashMap;
+use cgmath::{Vector3, Point3, Quaternion, Deg, Euler, Zero, Rotation3};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::color::color_for_index;
 
 /// Physics body data that can be easily extracted for rendering
 #[derive(Debug, Clone)]
@@ -10,6 +13,249 @@ pub struct PhysicsBody {
     pub linear_velocity: Vector3<f32>,
     pub angular_velocity: Vector3<f32>,
     pub is_dynamic: bool,
+    pub alpha: f32, // Opacity used when rendering, 1.0 is fully opaque
+    pub color: [f32; 3], // Tint multiplied with the texture when rendering
+    pub shape: BodyShape,
+}
+
+/// Collision shape of a body, cached so it can be drawn without going through Rapier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyShape {
+    Cuboid { half_extents: Vector3<f32> },
+    Ball { radius: f32 },
+}
+
+impl BodyShape {
+    /// Shape of a Rapier collider, `None` for shapes we don't mirror (e.g. triangle meshes)
+    fn from_collider(collider: &Collider) -> Option<Self> {
+        let shape = collider.shape();
+        if let Some(cuboid) = shape.as_cuboid() {
+            let half = cuboid.half_extents;
+            Some(BodyShape::Cuboid { half_extents: Vector3::new(half.x, half.y, half.z) })
+        } else {
+            shape.as_ball().map(|ball| BodyShape::Ball { radius: ball.radius })
+        }
+    }
+
+    /// Full width, height and depth of the shape's bounding box
+    pub fn size(&self) -> Vector3<f32> {
+        match self {
+            BodyShape::Cuboid { half_extents } => half_extents * 2.0,
+            BodyShape::Ball { radius } => Vector3::new(radius * 2.0, radius * 2.0, radius * 2.0),
+        }
+    }
+
+    pub fn kind(&self) -> ShapeKind {
+        match self {
+            BodyShape::Cuboid { .. } => ShapeKind::Cuboid,
+            BodyShape::Ball { .. } => ShapeKind::Ball,
+        }
+    }
+}
+
+/// Which kind of shape a body has, without its dimensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+    Cuboid,
+    Ball,
+}
+
+/// Read-only snapshot of a body for listing in a UI
+#[derive(Debug, Clone, Copy)]
+pub struct BodySummary {
+    pub handle: RigidBodyHandle,
+    pub position: Vector3<f32>,
+    pub rotation_euler: Vector3<f32>, // Degrees around x, y and z, what a transform panel would edit
+    pub shape_kind: ShapeKind,
+    pub is_sleeping: bool,
+}
+
+/// Dimensions of the ground slab, so whatever draws it can match the collider
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ground {
+    pub half_extent_xz: f32,
+    pub thickness: f32,
+    pub y: f32, // Height of the top surface
+}
+
+/// A collider that isn't attached to any body, like the ground
+#[derive(Debug, Clone, Copy)]
+pub struct StaticCollider {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub shape: BodyShape,
+}
+
+/// Optional settings for spawning a cube with `PhysicsWorld::add_cube_ex`
+/// Anything left unset falls back to Rapier's defaults
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CubeOptions {
+    pub linear_damping: Option<f32>,
+    pub angular_damping: Option<f32>,
+    pub ccd_enabled: bool,
+    pub mass: Option<f32>, // Total mass in kg, otherwise derived from the collider's volume
+    pub restitution: Option<f32>, // Bounciness, 0 stops dead and 1 bounces back to the same height
+}
+
+impl CubeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn linear_damping(mut self, damping: f32) -> Self {
+        self.linear_damping = Some(damping);
+        self
+    }
+
+    pub fn angular_damping(mut self, damping: f32) -> Self {
+        self.angular_damping = Some(damping);
+        self
+    }
+
+    pub fn ccd_enabled(mut self, enabled: bool) -> Self {
+        self.ccd_enabled = enabled;
+        self
+    }
+
+    pub fn mass(mut self, mass: f32) -> Self {
+        self.mass = Some(mass);
+        self
+    }
+
+    pub fn restitution(mut self, restitution: f32) -> Self {
+        self.restitution = Some(restitution);
+        self
+    }
+}
+
+/// Two colliders that started touching during a step
+#[derive(Debug, Clone, Copy)]
+pub struct Collision {
+    pub collider1: ColliderHandle,
+    pub collider2: ColliderHandle,
+    pub point: Vector3<f32>, // World-space contact point, the midpoint between the colliders if there's none (sensors)
+    pub impulse: f32, // Largest contact impulse between them over the step they started and the next, see contact_impulses
+}
+
+/// How hard two colliders pushed on each other during the last step
+#[derive(Debug, Clone, Copy)]
+pub struct ContactImpulse {
+    pub collider1: ColliderHandle,
+    pub collider2: ColliderHandle,
+    pub body1: Option<RigidBodyHandle>, // None for colliders without a body, like the ground
+    pub body2: Option<RigidBodyHandle>,
+    pub impulse: f32, // Total normal impulse over every contact point, in N*s
+}
+
+// Collisions kept until take_collisions, so nobody draining them doesn't grow the list forever
+const MAX_PENDING_COLLISIONS: usize = 1024;
+// Same for despawned handles kept until take_despawned, a long headless run with a kill plane would otherwise pile them up
+const MAX_PENDING_DESPAWNED: usize = 1024;
+
+// Rapier reports events through a shared reference, so they're gathered behind a lock
+#[derive(Default)]
+struct CollisionCollector {
+    started: Mutex<Vec<Collision>>,
+}
+
+impl EventHandler for CollisionCollector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        event: CollisionEvent,
+        contact_pair: Option<&ContactPair>,
+    ) {
+        let CollisionEvent::Started(collider1, collider2, flags) = event else {
+            return;
+        };
+        // Overlapping a sensor isn't a hit
+        if flags.contains(CollisionEventFlags::SENSOR) {
+            return;
+        }
+        let contact = contact_pair
+            .and_then(|pair| pair.manifolds.iter().flat_map(|manifold| manifold.data.solver_contacts.iter()).next())
+            .map(|contact| contact.point.coords);
+        let point = contact.or_else(|| {
+            let a = colliders.get(collider1)?.translation();
+            let b = colliders.get(collider2)?.translation();
+            Some((a + b) / 2.0)
+        });
+        if let Some(point) = point {
+            let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+            started.push(Collision { collider1, collider2, point: Vector3::new(point.x, point.y, point.z), impulse: 0.0 });
+        }
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        _dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        _contact_pair: &ContactPair,
+        _total_force_magnitude: Real,
+    ) {
+    }
+}
+
+/// Something done to a `PhysicsWorld` that can be recorded and replayed
+/// Handles refer to bodies and joints created by earlier commands in the same recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    AddGround { half_extent_xz: f32, thickness: f32, y: f32 },
+    AddSensor { position: Vector3<f32>, half_extents: Vector3<f32> },
+    Spawn { position: Vector3<f32>, size: f32, options: CubeOptions },
+    SpawnBox { position: Vector3<f32>, half_extents: Vector3<f32> },
+    RemoveBody { handle: RigidBodyHandle },
+    ClearBodies,
+    DespawnBelow { y_threshold: f32 },
+    SetKillPlane { y_threshold: Option<f32> },
+    SetMaxBodies { max_bodies: Option<usize> },
+    SetBodyTransform { handle: RigidBodyHandle, position: Vector3<f32>, rotation: Quaternion<f32>, reset_velocity: bool },
+    SetCcdEnabled { handle: RigidBodyHandle, enabled: bool },
+    SetRestitution { handle: RigidBodyHandle, restitution: f32 },
+    SetLinearDamping { handle: RigidBodyHandle, damping: f32 },
+    SetAngularDamping { handle: RigidBodyHandle, damping: f32 },
+    SetMass { handle: RigidBodyHandle, mass: f32 },
+    ApplyForce { handle: RigidBodyHandle, force: Vector3<f32> },
+    ApplyImpulseAtPoint { handle: RigidBodyHandle, impulse: Vector3<f32>, point: Vector3<f32> },
+    AddFixedJoint { a: RigidBodyHandle, b: RigidBodyHandle, anchor: Vector3<f32> },
+    AddBallJoint { a: RigidBodyHandle, b: RigidBodyHandle, anchor_a: Vector3<f32>, anchor_b: Vector3<f32> },
+    AddRevoluteJoint {
+        a: RigidBodyHandle,
+        b: RigidBodyHandle,
+        anchor_a: Vector3<f32>,
+        anchor_b: Vector3<f32>,
+        axis: Vector3<f32>,
+    },
+    RemoveJoint { handle: ImpulseJointHandle },
+    SetGravity { gravity: Vector3<f32> },
+    SetTimestep { dt: f32 },
+    SetSolverIterations { iterations: usize },
+    SetErp { erp: f32 },
+    SetMaxCcdSubsteps { substeps: usize },
+    SetDeterministic { deterministic: bool },
+    Step { delta_time: f32 },
+}
+
+// What's being logged between start_recording and stop_recording
+#[derive(Debug, Default)]
+struct Recording {
+    commands: Vec<Command>,
+    // The first thing done that can't be replayed, e.g. a trimesh or user hooks, makes the recording unusable
+    unrecordable: Option<&'static str>,
+}
+
+// Copy a body's pose and velocities from Rapier into our cached copy
+fn copy_motion(rigid_body: &RigidBody, body_data: &mut PhysicsBody) {
+    let position = rigid_body.translation();
+    let rotation = rigid_body.rotation();
+    let linear_velocity = rigid_body.linvel();
+    let angular_velocity = rigid_body.angvel();
+    body_data.position = Vector3::new(position.x, position.y, position.z);
+    body_data.rotation = Quaternion::new(rotation.w, rotation.i, rotation.j, rotation.k);
+    body_data.linear_velocity = Vector3::new(linear_velocity.x, linear_velocity.y, linear_velocity.z);
+    body_data.angular_velocity = Vector3::new(angular_velocity.x, angular_velocity.y, angular_velocity.z);
 }
 
 /// Wrapper around Rapier3D physics world for easy integration
@@ -23,10 +269,48 @@ pub struct PhysicsWorld {
     impulse_joint_set: ImpulseJointSet,
     multibody_joint_set: MultibodyJointSet,
     ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline, // Updated by every step, for ray casts
     gravity: Vector<f32>,
     integration_parameters: IntegrationParameters,
     // Mapping from Rapier handle to our physics body data
     body_data: HashMap<RigidBodyHandle, PhysicsBody>,
+    // Number of steps taken and simulated seconds since creation (or the last reset_clock)
+    step_count: u64,
+    elapsed_time: f32,
+    // Bodies spawned so far, each one's index picks its default color
+    spawn_count: usize,
+    ground: Option<Ground>,
+    // Bodies falling below this height are removed every step, None disables the kill plane
+    kill_plane_y: Option<f32>,
+    // Handles removed by the kill plane or evicted by max_bodies since the last take_despawned
+    despawned: Vec<RigidBodyHandle>,
+    // Commands logged since start_recording, None when not recording
+    recording: Option<Recording>,
+    // Collisions that started since the last take_collisions
+    collisions: Vec<Collision>,
+    // Collisions that started in the last step, held for one more step since the contact
+    // usually starts just before the bodies actually hit
+    starting_collisions: Vec<Collision>,
+    // Contact impulses of the last step only, replaced every step
+    contact_impulses: Vec<ContactImpulse>,
+    // Walk bodies in handle order rather than HashMap order, see set_deterministic
+    deterministic: bool,
+    // Spawning past this many bodies evicts the oldest, None for no limit
+    max_bodies: Option<usize>,
+    // Bodies oldest first, for eviction. Removed bodies are skipped rather than searched for on every removal
+    spawn_order: VecDeque<RigidBodyHandle>,
+}
+
+// Thickness of the default ground, a body has to move this far in one step to tunnel through it
+const DEFAULT_GROUND_THICKNESS: f32 = 2.0;
+
+// Default kill plane height, far enough below the ground that nothing resting is affected
+const DEFAULT_KILL_PLANE_Y: f32 = -100.0;
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PhysicsWorld {
@@ -46,33 +330,253 @@ impl PhysicsWorld {
             impulse_joint_set: ImpulseJointSet::new(),
             multibody_joint_set: MultibodyJointSet::new(),
             ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
             gravity,
             integration_parameters,
             body_data: HashMap::new(),
+            step_count: 0,
+            elapsed_time: 0.0,
+            spawn_count: 0,
+            ground: None,
+            kill_plane_y: Some(DEFAULT_KILL_PLANE_Y),
+            despawned: Vec::new(),
+            recording: None,
+            collisions: Vec::new(),
+            starting_collisions: Vec::new(),
+            contact_impulses: Vec::new(),
+            deterministic: false,
+            max_bodies: None,
+            spawn_order: VecDeque::new(),
         }
     }
 
     /// Add a static ground plane at y = 0
+    /// It's a slab reaching DEFAULT_GROUND_THICKNESS below the surface so fast falling bodies can't skip
+    /// through it in one step. Bodies faster than that per step still need CCD (`CubeOptions::ccd_enabled`)
     pub fn add_ground(&mut self) -> ColliderHandle {
-        let ground_collider = ColliderBuilder::cuboid(100.0, 0.1, 100.0)
-            .translation(vector![0.0, -0.1, 0.0])
+        self.add_ground_sized(100.0, DEFAULT_GROUND_THICKNESS, 0.0)
+    }
+
+    /// Add a static ground slab whose top surface is at height `y`
+    /// It spans `half_extent_xz` each way from the origin and extends `thickness` below `y`,
+    /// a thicker slab makes it harder for fast bodies to tunnel through
+    pub fn add_ground_sized(&mut self, half_extent_xz: f32, thickness: f32, y: f32) -> ColliderHandle {
+        self.record(Command::AddGround { half_extent_xz, thickness, y });
+        let half_thickness = thickness / 2.0;
+        let ground_collider = ColliderBuilder::cuboid(half_extent_xz, half_thickness, half_extent_xz)
+            .translation(vector![0.0, y - half_thickness, 0.0])
             .build();
-        
+
+        self.ground = Some(Ground { half_extent_xz, thickness, y });
         self.collider_set.insert(ground_collider)
     }
 
+    /// Size of the ground added last, if any
+    pub fn ground(&self) -> Option<Ground> {
+        self.ground
+    }
+
+    /// Add an invisible box that detects bodies overlapping it without pushing them
+    /// Poll it with `bodies_in_sensor`, e.g. for a goal zone
+    pub fn add_sensor(&mut self, position: Vector3<f32>, half_extents: Vector3<f32>) -> ColliderHandle {
+        self.record(Command::AddSensor { position, half_extents });
+        let sensor = ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            .translation(vector![position.x, position.y, position.z])
+            .sensor(true)
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .build();
+
+        self.collider_set.insert(sensor)
+    }
+
+    /// Bodies currently overlapping the sensor `sensor`, as of the last step
+    pub fn bodies_in_sensor(&self, sensor: ColliderHandle) -> Vec<RigidBodyHandle> {
+        self.narrow_phase
+            .intersection_pairs_with(sensor)
+            .filter(|(_, _, intersecting)| *intersecting)
+            .filter_map(|(collider1, collider2, _)| {
+                let other = if collider1 == sensor { collider2 } else { collider1 };
+                self.collider_set.get(other)?.parent()
+            })
+            .collect()
+    }
+
+    /// Add static collision geometry from a triangle mesh (e.g. terrain loaded from an OBJ)
+    /// Use `Model::collision_geometry` to get the vertices and indices from a loaded model
+    /// Can't be recorded, see `stop_recording`
+    /// Returns None and adds nothing if there are no triangles or an index is past the end of `vertices`
+    pub fn add_trimesh_static(&mut self, vertices: &[Point3<f32>], indices: &[[u32; 3]]) -> Option<ColliderHandle> {
+        // Rapier panics on either, so catch them here
+        if indices.is_empty() {
+            log::warn!("Skipping a trimesh collider with no triangles");
+            return None;
+        }
+        if let Some(index) = indices.iter().flatten().find(|index| **index as usize >= vertices.len()) {
+            log::warn!("Skipping a trimesh collider, index {} is past its {} vertices", index, vertices.len());
+            return None;
+        }
+        self.record_unrecordable("add_trimesh_static");
+        let points = vertices
+            .iter()
+            .map(|v| point![v.x, v.y, v.z])
+            .collect::<Vec<_>>();
+
+        let trimesh_collider = ColliderBuilder::trimesh(points, indices.to_vec())
+            .build();
+
+        Some(self.collider_set.insert(trimesh_collider))
+    }
+
     /// Add a dynamic cube at the specified position
     pub fn add_cube(&mut self, position: Vector3<f32>, size: f32) -> RigidBodyHandle {
+        self.add_cube_ex(position, size, CubeOptions::new())
+    }
+
+    /// Add a dynamic box with different sizes along each axis, e.g. a plank or a wall
+    pub fn add_box(&mut self, position: Vector3<f32>, half_extents: Vector3<f32>) -> RigidBodyHandle {
+        self.record(Command::SpawnBox { position, half_extents });
+        self.insert_cuboid(RigidBodyBuilder::dynamic(), position, half_extents, None)
+    }
+
+    /// Add a dynamic cube with an explicit mass instead of one derived from its size
+    /// e.g. a heavy cube that can crash through a stack of light ones
+    pub fn add_cube_with_mass(&mut self, position: Vector3<f32>, size: f32, mass: f32) -> RigidBodyHandle {
+        self.add_cube_ex(position, size, CubeOptions::new().mass(mass))
+    }
+
+    /// Add a dynamic cube with continuous collision detection enabled
+    /// CCD keeps bodies from tunneling through thin colliders (like the ground) when they move
+    /// far enough in one step to skip past them, e.g. cubes dropped from a great height or flung at high velocity
+    pub fn add_cube_ccd(&mut self, position: Vector3<f32>, size: f32) -> RigidBodyHandle {
+        self.add_cube_ex(position, size, CubeOptions::new().ccd_enabled(true))
+    }
+
+    /// Enable or disable continuous collision detection on an existing body
+    pub fn set_ccd_enabled(&mut self, handle: RigidBodyHandle, enabled: bool) {
+        self.record(Command::SetCcdEnabled { handle, enabled });
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.enable_ccd(enabled);
+        }
+    }
+
+    /// Add a dynamic cube configured by `options`
+    /// e.g. `world.add_cube_ex(position, 1.0, CubeOptions::new().linear_damping(0.5))`
+    pub fn add_cube_ex(&mut self, position: Vector3<f32>, size: f32, options: CubeOptions) -> RigidBodyHandle {
+        self.record(Command::Spawn { position, size, options });
+
+        let mut rigid_body_builder = RigidBodyBuilder::dynamic().ccd_enabled(options.ccd_enabled);
+        if let Some(damping) = options.linear_damping {
+            rigid_body_builder = rigid_body_builder.linear_damping(damping);
+        }
+        if let Some(damping) = options.angular_damping {
+            rigid_body_builder = rigid_body_builder.angular_damping(damping);
+        }
+
+        let half = size / 2.0;
+        let handle = self.insert_cuboid(rigid_body_builder, position, Vector3::new(half, half, half), options.mass);
+        if let Some(restitution) = options.restitution {
+            self.set_restitution(handle, restitution);
+        }
+        handle
+    }
+
+    /// Choose which `PhysicsHooks` callbacks run for a body's colliders during `step_with_hooks`
+    /// Can't be recorded, see `stop_recording`
+    pub fn set_active_hooks(&mut self, handle: RigidBodyHandle, hooks: ActiveHooks) {
+        self.record_unrecordable("set_active_hooks");
+        let Some(rigid_body) = self.rigid_body_set.get(handle) else {
+            return;
+        };
+        for collider_handle in rigid_body.colliders() {
+            if let Some(collider) = self.collider_set.get_mut(*collider_handle) {
+                collider.set_active_hooks(hooks);
+            }
+        }
+    }
+
+    /// Set how bouncy a body is, 0 stops dead and 1 bounces back to the same height
+    pub fn set_restitution(&mut self, handle: RigidBodyHandle, restitution: f32) {
+        self.record(Command::SetRestitution { handle, restitution });
+        let Some(rigid_body) = self.rigid_body_set.get(handle) else {
+            return;
+        };
+        for collider_handle in rigid_body.colliders() {
+            if let Some(collider) = self.collider_set.get_mut(*collider_handle) {
+                collider.set_restitution(restitution);
+                // Rapier averages the two colliders' restitution by default, which would halve the bounce off the ground
+                collider.set_restitution_combine_rule(CoefficientCombineRule::Max);
+            }
+        }
+    }
+
+    /// Set how quickly a body loses linear velocity when nothing is touching it
+    pub fn set_linear_damping(&mut self, handle: RigidBodyHandle, damping: f32) {
+        self.record(Command::SetLinearDamping { handle, damping });
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_linear_damping(damping);
+        }
+    }
+
+    /// Set how quickly a body loses angular velocity when nothing is touching it
+    pub fn set_angular_damping(&mut self, handle: RigidBodyHandle, damping: f32) {
+        self.record(Command::SetAngularDamping { handle, damping });
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_angular_damping(damping);
+        }
+    }
+
+    /// Set the total mass of a body in kg, spread evenly over its colliders
+    pub fn set_mass(&mut self, handle: RigidBodyHandle, mass: f32) {
+        self.record(Command::SetMass { handle, mass });
+        let Some(rigid_body) = self.rigid_body_set.get_mut(handle) else {
+            return;
+        };
+        let colliders = rigid_body.colliders().to_vec();
+        if colliders.is_empty() {
+            return;
+        }
+        for collider_handle in &colliders {
+            if let Some(collider) = self.collider_set.get_mut(*collider_handle) {
+                collider.set_mass(mass / colliders.len() as f32);
+            }
+        }
+        // Rapier only folds collider mass into the body on the next step, do it now so get_mass is current
+        rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+        rigid_body.wake_up(true);
+    }
+
+    /// Total mass of a body in kg
+    pub fn get_mass(&self, handle: RigidBodyHandle) -> Option<f32> {
+        self.rigid_body_set.get(handle).map(|rigid_body| rigid_body.mass())
+    }
+
+    /// Insert a box collider attached to a rigid body built from `rigid_body_builder`
+    /// `mass` overrides the density-derived mass when set
+    fn insert_cuboid(
+        &mut self,
+        rigid_body_builder: RigidBodyBuilder,
+        position: Vector3<f32>,
+        half_extents: Vector3<f32>,
+        mass: Option<f32>,
+    ) -> RigidBodyHandle {
+        if let Some(max_bodies) = self.max_bodies {
+            self.evict_down_to(max_bodies - 1);
+        }
+
         // Create rigid body
-        let rigid_body = RigidBodyBuilder::dynamic()
+        let rigid_body = rigid_body_builder
             .translation(vector![position.x, position.y, position.z])
             .build();
         
         let rigid_body_handle = self.rigid_body_set.insert(rigid_body);
         
         // Create collider
-        let collider = ColliderBuilder::cuboid(size / 2.0, size / 2.0, size / 2.0)
-            .build();
+        let mut collider_builder = ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            .active_events(ActiveEvents::COLLISION_EVENTS); // For take_collisions
+        if let Some(mass) = mass {
+            collider_builder = collider_builder.mass(mass);
+        }
+        let collider = collider_builder.build();
         
         self.collider_set.insert_with_parent(
             collider,
@@ -87,16 +591,71 @@ impl PhysicsWorld {
             linear_velocity: Vector3::zero(),
             angular_velocity: Vector3::zero(),
             is_dynamic: true,
+            alpha: 1.0,
+            color: color_for_index(self.spawn_count),
+            shape: BodyShape::Cuboid { half_extents },
         });
         
+        self.spawn_count += 1;
+        self.spawn_order.push_back(rigid_body_handle);
+        // Drop handles of bodies removed some other way once they start to pile up
+        if self.spawn_order.len() > 2 * self.body_data.len() + 16 {
+            let body_data = &self.body_data;
+            self.spawn_order.retain(|handle| body_data.contains_key(handle));
+        }
+
         rigid_body_handle
     }
 
+    /// Spawn a rows x cols grid of cubes at the given height, centered on the origin
+    /// Bodies are created row by row, so the returned handles are always in the same order
+    pub fn spawn_grid(&mut self, rows: u32, cols: u32, spacing: f32, height: f32, size: f32) -> Vec<RigidBodyHandle> {
+        // Offsets that make the grid straddle the origin on both axes
+        let x_offset = cols.saturating_sub(1) as f32 * spacing / 2.0;
+        let z_offset = rows.saturating_sub(1) as f32 * spacing / 2.0;
+
+        let mut handles = Vec::with_capacity(rows as usize * cols as usize);
+        for z in 0..rows {
+            for x in 0..cols {
+                let position = Vector3::new(
+                    x as f32 * spacing - x_offset,
+                    height,
+                    z as f32 * spacing - z_offset,
+                );
+                handles.push(self.add_cube(position, size));
+            }
+        }
+
+        handles
+    }
+
     /// Step the physics simulation
-    pub fn step(&mut self, _delta_time: f32) {
-        // Create a physics hooks object
-        let physics_hooks = ();
-        let event_handler = ();
+    pub fn step(&mut self, delta_time: f32) {
+        self.record(Command::Step { delta_time });
+        self.step_unrecorded(&());
+    }
+
+    /// Like `step`, but Rapier calls `hooks` while it steps, e.g. to filter contacts or edit them
+    /// (one-way platforms, conveyor belts). Within one step it calls `filter_contact_pair` and
+    /// `filter_intersection_pair` when the broad phase finds a new pair, then `modify_solver_contacts`
+    /// for touching pairs just before solving. Only colliders opted in with `set_active_hooks` get these calls.
+    /// Hooks are code, so a recording that steps with them can't be replayed, see `stop_recording`
+    pub fn step_with_hooks<H: PhysicsHooks>(&mut self, _delta_time: f32, hooks: &H) {
+        // Like step, Rapier advances by its own timestep (see set_timestep) whatever delta_time says
+        self.record_unrecordable("step_with_hooks");
+        self.step_unrecorded(hooks);
+    }
+
+    fn step_unrecorded<H: PhysicsHooks>(&mut self, hooks: &H) {
+
+        let event_handler = CollisionCollector::default();
+        // Velocities going into the step, for estimating how hard contacts hit
+        let velocities_before = self
+            .rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .map(|(handle, body)| (handle, *body.linvel()))
+            .collect::<HashMap<_, _>>();
         
         // Step the physics simulation
         let gravity = self.gravity;
@@ -120,30 +679,532 @@ impl PhysicsWorld {
             &mut self.impulse_joint_set,
             &mut self.multibody_joint_set,
             &mut self.ccd_solver,
-            None,
-            &physics_hooks,
+            Some(&mut self.query_pipeline),
+            hooks,
             &event_handler,
         );
         
         // Update our cached physics body data from Rapier
         self.update_body_data();
+
+        self.update_contact_impulses(&velocities_before);
+        let mut started = event_handler.started.into_inner().unwrap_or_else(|e| e.into_inner());
+        for collision in &mut started {
+            collision.impulse = self.contact_impulse_between(collision.collider1, collision.collider2);
+        }
+        let mut held = std::mem::replace(&mut self.starting_collisions, started);
+        for collision in &mut held {
+            collision.impulse = collision.impulse.max(self.contact_impulse_between(collision.collider1, collision.collider2));
+        }
+        let room = MAX_PENDING_COLLISIONS.saturating_sub(self.collisions.len());
+        self.collisions.extend(held.into_iter().take(room));
+
+        // Drop anything that fell off the world so it doesn't fall forever
+        if let Some(y_threshold) = self.kill_plane_y {
+            let removed = self.remove_below(y_threshold);
+            self.note_despawned(removed);
+        }
+
+        // Rapier advances by its own fixed dt, so that's the time that was actually simulated
+        self.step_count += 1;
+        self.elapsed_time += self.integration_parameters.dt;
+    }
+
+    /// Set the gravity vector in m/s^2
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.record(Command::SetGravity { gravity });
+        self.gravity = vector![gravity.x, gravity.y, gravity.z];
+        // Sleeping bodies wouldn't notice the change otherwise
+        for (_, rigid_body) in self.rigid_body_set.iter_mut() {
+            rigid_body.wake_up(true);
+        }
+    }
+
+    pub fn gravity(&self) -> Vector3<f32> {
+        Vector3::new(self.gravity.x, self.gravity.y, self.gravity.z)
+    }
+
+    /// Collisions that started since the last call, oldest first
+    /// Each one shows up a step after it started, once its impulse includes the impact
+    /// Only cube and box colliders report them, up to MAX_PENDING_COLLISIONS are kept
+    pub fn take_collisions(&mut self) -> Vec<Collision> {
+        std::mem::take(&mut self.collisions)
+    }
+
+    /// Contact impulses from the most recent step, one entry per touching pair
+    /// Resting bodies show up too with small impulses (their weight times the timestep), impacts stand out
+    pub fn contact_impulses(&self) -> &[ContactImpulse] {
+        &self.contact_impulses
+    }
+
+    /// Largest impulse on any of `handle`'s contacts during the most recent step, 0 if it touched nothing
+    pub fn max_contact_impulse(&self, handle: RigidBodyHandle) -> f32 {
+        self.contact_impulses
+            .iter()
+            .filter(|contact| contact.body1 == Some(handle) || contact.body2 == Some(handle))
+            .map(|contact| contact.impulse)
+            .fold(0.0, f32::max)
+    }
+
+    /// Impulse between two colliders during the most recent step, 0 if they didn't touch
+    pub fn contact_impulse_between(&self, collider1: ColliderHandle, collider2: ColliderHandle) -> f32 {
+        self.contact_impulses
+            .iter()
+            .find(|contact| {
+                (contact.collider1 == collider1 && contact.collider2 == collider2)
+                    || (contact.collider1 == collider2 && contact.collider2 == collider1)
+            })
+            .map_or(0.0, |contact| contact.impulse)
+    }
+
+    // Rapier only keeps the impulses of the last solver substep, and an impact is usually
+    // absorbed in the first one, so also estimate it from each body's change in velocity
+    // along the contact normal, minus what gravity added. Rotation is ignored.
+    fn update_contact_impulses(&mut self, velocities_before: &HashMap<RigidBodyHandle, Vector<f32>>) {
+        self.contact_impulses.clear();
+        let dt = self.integration_parameters.dt;
+        for pair in self.narrow_phase.contact_pairs() {
+            if !pair.has_any_active_contact {
+                continue;
+            }
+            let parent = |collider| self.collider_set.get(collider).and_then(|collider| collider.parent());
+            let (body1, body2) = (parent(pair.collider1), parent(pair.collider2));
+
+            let solver_impulse = pair
+                .manifolds
+                .iter()
+                .flat_map(|manifold| manifold.points.iter())
+                .map(|point| point.data.impulse)
+                .sum::<f32>();
+            let normal = pair.manifolds.iter().find(|manifold| !manifold.points.is_empty()).map(|manifold| manifold.data.normal);
+            let velocity_impulse = |body: Option<RigidBodyHandle>| {
+                let (Some(handle), Some(normal)) = (body, normal) else {
+                    return 0.0;
+                };
+                let (Some(rigid_body), Some(before)) = (self.rigid_body_set.get(handle), velocities_before.get(&handle)) else {
+                    return 0.0;
+                };
+                let change = rigid_body.linvel() - before - self.gravity * rigid_body.gravity_scale() * dt;
+                rigid_body.mass() * change.dot(&normal).abs()
+            };
+            let impulse = solver_impulse.max(velocity_impulse(body1)).max(velocity_impulse(body2));
+            if impulse <= 0.0 {
+                continue;
+            }
+            self.contact_impulses.push(ContactImpulse {
+                collider1: pair.collider1,
+                collider2: pair.collider2,
+                body1,
+                body2,
+                impulse,
+            });
+        }
+    }
+
+    /// Start logging everything that changes how the world simulates, dropping anything recorded before
+    /// Colors, alpha and the clock aren't recorded since they don't affect the simulation
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::default());
+    }
+
+    /// Stop logging and return what was recorded
+    /// Fails if something that can't be replayed was done meanwhile: `add_trimesh_static`,
+    /// `set_active_hooks` or `step_with_hooks`
+    pub fn stop_recording(&mut self) -> anyhow::Result<Vec<Command>> {
+        let recording = self.recording.take().unwrap_or_default();
+        match recording.unrecordable {
+            Some(what) => anyhow::bail!("{} was used while recording and can't be replayed", what),
+            None => Ok(recording.commands),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Run recorded commands in order
+    /// Rapier is deterministic for a fixed timestep, so replaying a recording that started on a fresh world
+    /// against another fresh world with the same settings gives the same body positions
+    pub fn replay(&mut self, commands: &[Command]) {
+        for command in commands {
+            match *command {
+                Command::AddGround { half_extent_xz, thickness, y } => {
+                    self.add_ground_sized(half_extent_xz, thickness, y);
+                }
+                Command::AddSensor { position, half_extents } => {
+                    self.add_sensor(position, half_extents);
+                }
+                Command::Spawn { position, size, options } => {
+                    self.add_cube_ex(position, size, options);
+                }
+                Command::SpawnBox { position, half_extents } => {
+                    self.add_box(position, half_extents);
+                }
+                Command::RemoveBody { handle } => self.remove_body(handle),
+                Command::ClearBodies => {
+                    self.clear_bodies();
+                }
+                Command::DespawnBelow { y_threshold } => {
+                    self.despawn_below(y_threshold);
+                }
+                Command::SetKillPlane { y_threshold } => self.set_kill_plane(y_threshold),
+                Command::SetMaxBodies { max_bodies } => self.set_max_bodies(max_bodies),
+                Command::SetBodyTransform { handle, position, rotation, reset_velocity } => {
+                    self.set_body_transform(handle, position, rotation, reset_velocity);
+                }
+                Command::SetCcdEnabled { handle, enabled } => self.set_ccd_enabled(handle, enabled),
+                Command::SetRestitution { handle, restitution } => self.set_restitution(handle, restitution),
+                Command::SetLinearDamping { handle, damping } => self.set_linear_damping(handle, damping),
+                Command::SetAngularDamping { handle, damping } => self.set_angular_damping(handle, damping),
+                Command::SetMass { handle, mass } => self.set_mass(handle, mass),
+                Command::ApplyForce { handle, force } => self.apply_force(handle, force),
+                Command::ApplyImpulseAtPoint { handle, impulse, point } => self.apply_impulse_at_point(handle, impulse, point),
+                Command::AddFixedJoint { a, b, anchor } => {
+                    self.add_fixed_joint(a, b, anchor);
+                }
+                Command::AddBallJoint { a, b, anchor_a, anchor_b } => {
+                    self.add_ball_joint(a, b, anchor_a, anchor_b);
+                }
+                Command::AddRevoluteJoint { a, b, anchor_a, anchor_b, axis } => {
+                    self.add_revolute_joint(a, b, anchor_a, anchor_b, axis);
+                }
+                Command::RemoveJoint { handle } => self.remove_joint(handle),
+                Command::SetGravity { gravity } => self.set_gravity(gravity),
+                Command::SetTimestep { dt } => self.set_timestep(dt),
+                Command::SetSolverIterations { iterations } => self.set_solver_iterations(iterations),
+                Command::SetErp { erp } => self.set_erp(erp),
+                Command::SetMaxCcdSubsteps { substeps } => self.set_max_ccd_substeps(substeps),
+                Command::SetDeterministic { deterministic } => self.set_deterministic(deterministic),
+                Command::Step { delta_time } => self.step(delta_time),
+            }
+        }
+    }
+
+    fn record(&mut self, command: Command) {
+        if let Some(recording) = &mut self.recording {
+            recording.commands.push(command);
+        }
+    }
+
+    // Note that the recording can't be replayed, keeping the first reason
+    fn record_unrecordable(&mut self, what: &'static str) {
+        if let Some(recording) = &mut self.recording {
+            recording.unrecordable.get_or_insert(what);
+        }
+    }
+
+    /// Make repeated runs of the same commands give bit-identical results
+    /// Bodies are then removed and listed in handle order instead of HashMap order, which differs between runs
+    /// and, through Rapier reusing freed slots, changes the order later bodies are simulated in.
+    /// Caveats: Rapier already runs single threaded here (its `parallel` feature is off), and results only
+    /// match on the same platform and build. Building with the `enhanced-determinism` feature extends that
+    /// across platforms at some speed cost. The timestep and solver settings must match between runs too
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.record(Command::SetDeterministic { deterministic });
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Handles of the bodies `filter` accepts, sorted by handle in deterministic mode
+    fn handles_matching(&self, filter: impl Fn(&PhysicsBody) -> bool) -> Vec<RigidBodyHandle> {
+        let mut handles = self
+            .body_data
+            .iter()
+            .filter(|(_, body)| filter(body))
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        if self.deterministic {
+            handles.sort_by_key(|handle| handle.into_raw_parts());
+        }
+        handles
+    }
+
+    /// Remove every body whose position is below `y_threshold`, returning their handles
+    pub fn despawn_below(&mut self, y_threshold: f32) -> Vec<RigidBodyHandle> {
+        self.record(Command::DespawnBelow { y_threshold });
+        self.remove_below(y_threshold)
+    }
+
+    // despawn_below without recording, for the kill plane which replays by itself as part of step
+    fn remove_below(&mut self, y_threshold: f32) -> Vec<RigidBodyHandle> {
+        let fallen = self.handles_matching(|body| body.position.y < y_threshold);
+
+        for handle in &fallen {
+            self.despawn(*handle);
+        }
+        fallen
+    }
+
+    /// Remove a body and its colliders from the world
+    pub fn remove_body(&mut self, handle: RigidBodyHandle) {
+        self.record(Command::RemoveBody { handle });
+        self.despawn(handle);
+    }
+
+    // remove_body without recording, for removals that are replayed as part of another command
+    fn despawn(&mut self, handle: RigidBodyHandle) {
+        self.rigid_body_set.remove(
+            handle,
+            &mut self.island_manager,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multibody_joint_set,
+            true,
+        );
+        self.body_data.remove(&handle);
+    }
+
+    /// Remove every dynamic body, leaving the ground and other static colliders, returns the removed handles
+    pub fn clear_bodies(&mut self) -> Vec<RigidBodyHandle> {
+        self.record(Command::ClearBodies);
+        let handles = self.handles_matching(|_| true);
+        for handle in &handles {
+            self.despawn(*handle);
+        }
+        self.spawn_order.clear();
+        handles
+    }
+
+    /// Weld two bodies together at the world-space point `anchor`, keeping their current relative pose
+    /// Returns `None` if either body doesn't exist
+    pub fn add_fixed_joint(&mut self, a: RigidBodyHandle, b: RigidBodyHandle, anchor: Vector3<f32>) -> Option<ImpulseJointHandle> {
+        self.record(Command::AddFixedJoint { a, b, anchor });
+        let anchor = Isometry::translation(anchor.x, anchor.y, anchor.z);
+        let frame_a = self.rigid_body_set.get(a)?.position().inv_mul(&anchor);
+        let frame_b = self.rigid_body_set.get(b)?.position().inv_mul(&anchor);
+
+        let joint = FixedJointBuilder::new()
+            .local_frame1(frame_a)
+            .local_frame2(frame_b);
+        Some(self.impulse_joint_set.insert(a, b, joint, true))
+    }
+
+    /// Join two bodies with a ball-and-socket joint, free to rotate in every direction
+    /// The anchors are in each body's local space and are kept in the same place
+    pub fn add_ball_joint(
+        &mut self,
+        a: RigidBodyHandle,
+        b: RigidBodyHandle,
+        anchor_a: Vector3<f32>,
+        anchor_b: Vector3<f32>,
+    ) -> Option<ImpulseJointHandle> {
+        self.record(Command::AddBallJoint { a, b, anchor_a, anchor_b });
+        if !self.rigid_body_set.contains(a) || !self.rigid_body_set.contains(b) {
+            return None;
+        }
+
+        let joint = SphericalJointBuilder::new()
+            .local_anchor1(point![anchor_a.x, anchor_a.y, anchor_a.z])
+            .local_anchor2(point![anchor_b.x, anchor_b.y, anchor_b.z]);
+        Some(self.impulse_joint_set.insert(a, b, joint, true))
+    }
+
+    /// Join two bodies with a hinge that only rotates around `axis` (in local space, doesn't need to be normalized)
+    /// e.g. a pendulum or a door
+    pub fn add_revolute_joint(
+        &mut self,
+        a: RigidBodyHandle,
+        b: RigidBodyHandle,
+        anchor_a: Vector3<f32>,
+        anchor_b: Vector3<f32>,
+        axis: Vector3<f32>,
+    ) -> Option<ImpulseJointHandle> {
+        self.record(Command::AddRevoluteJoint { a, b, anchor_a, anchor_b, axis });
+        if !self.rigid_body_set.contains(a) || !self.rigid_body_set.contains(b) {
+            return None;
+        }
+        let axis = UnitVector::try_new(vector![axis.x, axis.y, axis.z], f32::EPSILON)?;
+
+        let joint = RevoluteJointBuilder::new(axis)
+            .local_anchor1(point![anchor_a.x, anchor_a.y, anchor_a.z])
+            .local_anchor2(point![anchor_b.x, anchor_b.y, anchor_b.z]);
+        Some(self.impulse_joint_set.insert(a, b, joint, true))
+    }
+
+    /// Remove a joint, waking the bodies it connected
+    pub fn remove_joint(&mut self, handle: ImpulseJointHandle) {
+        self.record(Command::RemoveJoint { handle });
+        self.impulse_joint_set.remove(handle, true);
+    }
+
+    /// Height below which `step` removes bodies, `None` to keep everything
+    pub fn set_kill_plane(&mut self, y_threshold: Option<f32>) {
+        self.record(Command::SetKillPlane { y_threshold });
+        self.kill_plane_y = y_threshold;
+    }
+
+    pub fn kill_plane(&self) -> Option<f32> {
+        self.kill_plane_y
+    }
+
+    /// Limit how many bodies the world holds, `None` (the default) for no limit
+    /// Past the limit every new body evicts the oldest one, and lowering it evicts the oldest right away.
+    /// Evicted handles are reported by `take_despawned`, like those the kill plane removes. At least 1
+    pub fn set_max_bodies(&mut self, max_bodies: Option<usize>) {
+        self.record(Command::SetMaxBodies { max_bodies });
+        self.max_bodies = max_bodies.map(|max| max.max(1));
+        if let Some(max_bodies) = self.max_bodies {
+            self.evict_down_to(max_bodies);
+        }
+    }
+
+    pub fn max_bodies(&self) -> Option<usize> {
+        self.max_bodies
+    }
+
+    // Remove the oldest bodies until at most `count` are left
+    fn evict_down_to(&mut self, count: usize) {
+        while self.body_data.len() > count {
+            let Some(oldest) = self.spawn_order.pop_front() else {
+                break;
+            };
+            if self.body_data.contains_key(&oldest) {
+                self.despawn(oldest);
+                self.note_despawned([oldest]);
+            }
+        }
+    }
+
+    // Keep removed handles for take_despawned, dropping the newest past MAX_PENDING_DESPAWNED
+    fn note_despawned(&mut self, handles: impl IntoIterator<Item = RigidBodyHandle>) {
+        let room = MAX_PENDING_DESPAWNED.saturating_sub(self.despawned.len());
+        self.despawned.extend(handles.into_iter().take(room));
+    }
+
+    /// Handles the kill plane or `set_max_bodies` removed since the last call, so owners can forget them
+    /// Up to MAX_PENDING_DESPAWNED are kept, so a world nobody drains stays bounded
+    pub fn take_despawned(&mut self) -> Vec<RigidBodyHandle> {
+        std::mem::take(&mut self.despawned)
+    }
+
+    /// Length of one simulation step in seconds
+    pub fn timestep(&self) -> f32 {
+        self.integration_parameters.dt
+    }
+
+    /// Change the length of one simulation step in seconds, the default is 1/60
+    /// `State` steps the world in increments of this, so it also sets the physics rate
+    pub fn set_timestep(&mut self, dt: f32) {
+        self.record(Command::SetTimestep { dt });
+        if dt > 0.0 {
+            self.integration_parameters.dt = dt;
+        }
+    }
+
+    /// Solver iterations per step, more makes stacks steadier at the cost of speed (at least 1)
+    pub fn set_solver_iterations(&mut self, iterations: usize) {
+        self.record(Command::SetSolverIterations { iterations });
+        self.integration_parameters.num_solver_iterations =
+            std::num::NonZeroUsize::new(iterations).unwrap_or(std::num::NonZeroUsize::MIN);
+    }
+
+    pub fn solver_iterations(&self) -> usize {
+        self.integration_parameters.num_solver_iterations.get()
+    }
+
+    /// Error reduction parameter, the fraction of penetration fixed each step (0 to 1)
+    /// Higher values push overlapping bodies apart faster but can make contacts jitter
+    pub fn set_erp(&mut self, erp: f32) {
+        self.record(Command::SetErp { erp });
+        self.integration_parameters.erp = erp.clamp(0.0, 1.0);
+    }
+
+    pub fn erp(&self) -> f32 {
+        self.integration_parameters.erp
+    }
+
+    /// How many substeps CCD may take per step for fast bodies
+    pub fn set_max_ccd_substeps(&mut self, substeps: usize) {
+        self.record(Command::SetMaxCcdSubsteps { substeps });
+        self.integration_parameters.max_ccd_substeps = substeps;
+    }
+
+    /// All of Rapier's solver settings, for anything the setters above don't cover
+    pub fn integration_parameters(&self) -> &IntegrationParameters {
+        &self.integration_parameters
+    }
+
+    /// Number of times `step` has been called since creation or the last `reset_clock`
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    /// Total simulated time in seconds since creation or the last `reset_clock`
+    pub fn elapsed_time(&self) -> f32 {
+        self.elapsed_time
+    }
+
+    /// Reset the step counter and simulated time, e.g. when resetting the scene
+    pub fn reset_clock(&mut self) {
+        self.step_count = 0;
+        self.elapsed_time = 0.0;
     }
 
     /// Update our cached physics body data from Rapier
     fn update_body_data(&mut self) {
         for (handle, rigid_body) in self.rigid_body_set.iter() {
-            let position = rigid_body.translation();
-            let rotation = rigid_body.rotation();
-            let linear_velocity = rigid_body.linvel();
-            let angular_velocity = rigid_body.angvel();
-            
             if let Some(body_data) = self.body_data.get_mut(&handle) {
-                body_data.position = Vector3::new(position.x, position.y, position.z);
-                body_data.rotation = Quaternion::new(rotation.w, rotation.i, rotation.j, rotation.k);
-                body_data.linear_velocity = Vector3::new(linear_velocity.x, linear_velocity.y, linear_velocity.z);
-                body_data.angular_velocity = Vector3::new(angular_velocity.x, angular_velocity.y, angular_velocity.z);
+                copy_motion(rigid_body, body_data);
+            }
+        }
+    }
+
+    /// First body hit by a ray from `origin` along `direction`, and how far along the ray it was hit
+    /// Static geometry blocks the ray, so nothing hidden behind the ground is returned. Sensors are ignored
+    /// Colliders are tested where the last step left them
+    pub fn cast_ray(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<(RigidBodyHandle, f32)> {
+        let ray = Ray::new(point![origin.x, origin.y, origin.z], vector![direction.x, direction.y, direction.z]);
+        let filter = QueryFilter::default().exclude_sensors();
+        let (collider, distance) = self
+            .query_pipeline
+            .cast_ray(&self.rigid_body_set, &self.collider_set, &ray, max_distance, true, filter)?;
+        let handle = self.collider_set.get(collider)?.parent()?;
+        self.body_data.contains_key(&handle).then_some((handle, distance))
+    }
+
+    /// Move a body straight to `position` and `rotation` without simulating the motion in between, e.g. for
+    /// dragging it in an editor. With `reset_velocity` it arrives at rest, otherwise it keeps moving as before
+    /// `get_body` reflects the move right away. Returns false if the body doesn't exist
+    pub fn set_body_transform(
+        &mut self,
+        handle: RigidBodyHandle,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        reset_velocity: bool,
+    ) -> bool {
+        self.record(Command::SetBodyTransform { handle, position, rotation, reset_velocity });
+        let Some(rigid_body) = self.rigid_body_set.get_mut(handle) else {
+            return false;
+        };
+        rigid_body.set_translation(vector![position.x, position.y, position.z], true);
+        // Normalized here, so a quaternion that drifted from unit length doesn't skew the body
+        let rotation = nalgebra::Quaternion::new(rotation.s, rotation.v.x, rotation.v.y, rotation.v.z);
+        rigid_body.set_rotation(nalgebra::UnitQuaternion::from_quaternion(rotation), true);
+        if reset_velocity {
+            rigid_body.set_linvel(Vector::zeros(), true);
+            rigid_body.set_angvel(Vector::zeros(), true);
+        }
+        if let Some(body_data) = self.body_data.get_mut(&handle) {
+            copy_motion(rigid_body, body_data);
+        }
+        true
+    }
+
+    /// True when there is at least one dynamic body and every one is asleep, i.e. the scene has settled
+    /// An empty world isn't settled, so it keeps stepping. Applying a force or spawning a body wakes things up again
+    pub fn all_asleep(&self) -> bool {
+        let mut any = false;
+        let mut all = true;
+        for (_, rigid_body) in self.rigid_body_set.iter() {
+            if rigid_body.is_dynamic() {
+                any = true;
+                all &= rigid_body.is_sleeping();
             }
         }
+        any && all
     }
 
     /// Get all physics bodies for rendering
@@ -151,15 +1212,407 @@ impl PhysicsWorld {
         &self.body_data
     }
 
+    /// Colliders with no parent body (the ground and other static geometry)
+    /// Triangle meshes are skipped since they have no simple shape
+    pub fn static_colliders(&self) -> Vec<StaticCollider> {
+        self.collider_set
+            .iter()
+            .filter(|(_, collider)| collider.parent().is_none())
+            .filter_map(|(_, collider)| {
+                let shape = BodyShape::from_collider(collider)?;
+                let position = collider.translation();
+                let rotation = collider.rotation();
+                Some(StaticCollider {
+                    position: Vector3::new(position.x, position.y, position.z),
+                    rotation: Quaternion::new(rotation.w, rotation.i, rotation.j, rotation.k),
+                    shape,
+                })
+            })
+            .collect()
+    }
+
+    /// A snapshot of every body, for populating an object list (in handle order in deterministic mode)
+    pub fn body_summaries(&self) -> Vec<BodySummary> {
+        self.handles_matching(|_| true)
+            .into_iter()
+            .filter_map(|handle| Some((handle, self.body_data.get(&handle)?)))
+            .map(|(handle, body)| {
+                let euler = Euler::from(body.rotation);
+                BodySummary {
+                    handle,
+                    position: body.position,
+                    rotation_euler: Vector3::new(Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0),
+                    shape_kind: body.shape.kind(),
+                    is_sleeping: self.rigid_body_set.get(handle).is_some_and(|rigid_body| rigid_body.is_sleeping()),
+                }
+            })
+            .collect()
+    }
+
     /// Get a specific physics body by handle
     pub fn get_body(&self, handle: RigidBodyHandle) -> Option<&PhysicsBody> {
         self.body_data.get(&handle)
     }
 
+    /// Set how opaque a body is drawn, values below 1.0 make it translucent
+    pub fn set_alpha(&mut self, handle: RigidBodyHandle, alpha: f32) {
+        if let Some(body_data) = self.body_data.get_mut(&handle) {
+            body_data.alpha = alpha.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Set the tint a body is drawn with
+    pub fn set_color(&mut self, handle: RigidBodyHandle, color: [f32; 3]) {
+        if let Some(body_data) = self.body_data.get_mut(&handle) {
+            body_data.color = color;
+        }
+    }
+
     /// Apply a force to a rigid body
     pub fn apply_force(&mut self, handle: RigidBodyHandle, force: Vector3<f32>) {
+        self.record(Command::ApplyForce { handle, force });
         if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
             rigid_body.add_force(vector![force.x, force.y, force.z], true);
         }
     }
+
+    /// Apply an instantaneous impulse at a world-space point
+    /// Hitting a body away from its center of mass makes it spin, e.g. flicking a cube by its corner
+    pub fn apply_impulse_at_point(&mut self, handle: RigidBodyHandle, impulse: Vector3<f32>, point: Vector3<f32>) {
+        self.record(Command::ApplyImpulseAtPoint { handle, impulse, point });
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.apply_impulse_at_point(
+                vector![impulse.x, impulse.y, impulse.z],
+                point![point.x, point.y, point.z],
+                true,
+            );
+        }
+    }
+
+    /// Push every dynamic body within `radius` of `center` away from it
+    /// The impulse is `strength` (N*s) at the center and falls off linearly to 0 at `radius`. It's applied
+    /// where each body is closest to the blast, so bodies hit off-center start spinning too
+    /// A recording gets the impulses it applied
+    pub fn explode(&mut self, center: Vector3<f32>, radius: f32, strength: f32) {
+        if radius <= 0.0 {
+            return;
+        }
+        let blast = point![center.x, center.y, center.z];
+        for handle in self.handles_matching(|body| body.is_dynamic) {
+            let Some(rigid_body) = self.rigid_body_set.get(handle) else {
+                continue;
+            };
+            // Closest point on the body's colliders, its center if the blast is inside it
+            let closest = rigid_body
+                .colliders()
+                .iter()
+                .filter_map(|collider| self.collider_set.get(*collider))
+                .map(|collider| collider.shape().project_point(collider.position(), &blast, true))
+                .filter(|projection| !projection.is_inside)
+                .map(|projection| projection.point)
+                .min_by(|a, b| nalgebra::distance(a, &blast).total_cmp(&nalgebra::distance(b, &blast)))
+                .unwrap_or(*rigid_body.center_of_mass());
+
+            let offset = rigid_body.center_of_mass() - blast;
+            let distance = nalgebra::distance(&closest, &blast);
+            if distance > radius {
+                continue;
+            }
+            // Straight up when the blast is right at the center of mass
+            let direction = offset.try_normalize(f32::EPSILON).unwrap_or(Vector::y());
+            let impulse = direction * strength * (1.0 - distance / radius);
+            self.apply_impulse_at_point(
+                handle,
+                Vector3::new(impulse.x, impulse.y, impulse.z),
+                Vector3::new(closest.x, closest.y, closest.z),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn cube_falls_and_rests_on_ground() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground();
+        world.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        let cube = world.add_cube(Vector3::new(0.0, 10.0, 0.0), 1.0);
+
+        // Long enough to fall 10m at 60Hz and stop bouncing
+        for _ in 0..300 {
+            world.step(1.0 / 60.0);
+        }
+
+        let body = world.get_body(cube).expect("cube was removed");
+        // The ground's top is at y=0, so a 1m cube rests with its center at 0.5
+        assert!((body.position.y - 0.5).abs() < 0.05, "cube rests at y={}", body.position.y);
+        assert!(body.linear_velocity.magnitude() < 0.05, "cube still moving at {:?}", body.linear_velocity);
+    }
+
+    #[test]
+    fn fast_cube_does_not_tunnel_through_ground() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground();
+        world.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        // No CCD, the ground's thickness alone has to stop it
+        let cube = world.add_cube(Vector3::new(0.0, 50.0, 0.0), 1.0);
+
+        // Falling 50m takes a bit over 3 seconds, then give it time to settle
+        for _ in 0..600 {
+            world.step(1.0 / 60.0);
+        }
+
+        let body = world.get_body(cube).expect("cube fell through the ground and was removed");
+        assert!(body.position.y > 0.0, "cube ended up below the ground at y={}", body.position.y);
+        assert!((body.position.y - 0.5).abs() < 0.05, "cube rests at y={}", body.position.y);
+    }
+
+    #[test]
+    fn deterministic_runs_are_bit_identical() {
+        fn run() -> Vec<(RigidBodyHandle, [u32; 7])> {
+            let mut world = PhysicsWorld::new();
+            world.set_deterministic(true);
+            world.add_ground();
+            world.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+            world.spawn_grid(3, 3, 1.1, 2.0, 1.0);
+            // Off the edge of the ground, these fall past the kill plane and free their slots
+            for i in 0..4 {
+                world.add_cube(Vector3::new(150.0 + i as f32 * 2.0, 5.0, 0.0), 1.0);
+            }
+            for _ in 0..300 {
+                world.step(1.0 / 60.0);
+            }
+            // These reuse the freed slots
+            for i in 0..4 {
+                let cube = world.add_cube(Vector3::new(i as f32 * 0.3, 6.0 + i as f32 * 1.1, 0.2), 1.0);
+                world.apply_impulse_at_point(cube, Vector3::new(1.0, 0.0, 0.5), Vector3::new(0.0, 6.5, 0.0));
+            }
+            for _ in 0..200 {
+                world.step(1.0 / 60.0);
+            }
+
+            world
+                .body_summaries()
+                .into_iter()
+                .map(|summary| {
+                    let body = world.get_body(summary.handle).expect("summary of a missing body");
+                    let (p, r) = (body.position, body.rotation);
+                    let bits = [p.x, p.y, p.z, r.s, r.v.x, r.v.y, r.v.z].map(f32::to_bits);
+                    (summary.handle, bits)
+                })
+                .collect()
+        }
+
+        let first = run();
+        assert_eq!(first.len(), 13);
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn cube_stays_put_without_gravity() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground();
+        world.set_gravity(Vector3::zero());
+        let start = Vector3::new(1.0, 10.0, -2.0);
+        let cube = world.add_cube(start, 1.0);
+
+        for _ in 0..120 {
+            world.step(1.0 / 60.0);
+        }
+
+        let body = world.get_body(cube).expect("cube was removed");
+        assert!((body.position - start).magnitude() < 1e-4, "cube drifted to {:?}", body.position);
+    }
+
+    #[test]
+    fn max_bodies_evicts_the_oldest() {
+        let mut world = PhysicsWorld::new();
+        world.set_max_bodies(Some(3));
+        let handles = (0..5)
+            .map(|i| world.add_cube(Vector3::new(i as f32 * 2.0, 1.0, 0.0), 1.0))
+            .collect::<Vec<_>>();
+
+        assert_eq!(world.get_bodies().len(), 3);
+        assert_eq!(world.take_despawned(), handles[..2]);
+        for handle in &handles[2..] {
+            assert!(world.get_body(*handle).is_some());
+        }
+
+        // Lowering the limit evicts right away, oldest first
+        world.set_max_bodies(Some(1));
+        assert_eq!(world.take_despawned(), handles[2..4]);
+        assert!(world.get_body(handles[4]).is_some());
+    }
+
+    #[test]
+    fn explode_pushes_nearby_bodies_away() {
+        let mut world = PhysicsWorld::new();
+        let near = world.add_cube(Vector3::new(2.0, 0.0, 0.0), 1.0);
+        let far = world.add_cube(Vector3::new(-10.0, 0.0, 0.0), 1.0);
+
+        world.explode(Vector3::zero(), 5.0, 10.0);
+        world.step(1.0 / 60.0);
+
+        let pushed = world.get_body(near).unwrap().linear_velocity;
+        assert!(pushed.x > 1.0, "near cube moved at {:?}", pushed);
+        assert!(pushed.x.abs() > pushed.z.abs() * 10.0, "near cube went sideways at {:?}", pushed);
+        let untouched = world.get_body(far).unwrap().linear_velocity;
+        assert!(untouched.x.abs() < 1e-6 && untouched.z.abs() < 1e-6, "far cube moved at {:?}", untouched);
+    }
+
+    #[test]
+    fn set_body_transform_moves_the_body_immediately() {
+        let mut world = PhysicsWorld::new();
+        let cube = world.add_cube(Vector3::new(0.0, 5.0, 0.0), 1.0);
+        world.apply_impulse_at_point(cube, Vector3::new(3.0, 0.0, 0.0), Vector3::new(0.0, 5.5, 0.0));
+        world.step(1.0 / 60.0);
+
+        let target = Vector3::new(4.0, 2.0, -1.0);
+        let rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Deg(90.0));
+        assert!(world.set_body_transform(cube, target, rotation, true));
+
+        let body = world.get_body(cube).unwrap();
+        assert!((body.position - target).magnitude() < 1e-6, "cube is at {:?}", body.position);
+        assert!((body.rotation - rotation).magnitude() < 1e-5, "cube is rotated {:?}", body.rotation);
+        assert_eq!(body.linear_velocity, Vector3::zero());
+    }
+
+    #[test]
+    fn cast_ray_hits_the_nearest_body_and_not_through_the_ground() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground();
+        let near = world.add_cube(Vector3::new(0.0, 5.0, 0.0), 1.0);
+        world.add_cube(Vector3::new(0.0, 10.0, 0.0), 1.0);
+        world.step(1.0 / 60.0);
+
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let (hit, distance) = world.cast_ray(Point3::new(0.0, 7.0, 0.0), down, 100.0).unwrap();
+        assert_eq!(hit, near);
+        assert!((distance - 1.5).abs() < 0.01, "hit at {}", distance);
+        // The ground is in the way
+        assert_eq!(world.cast_ray(Point3::new(0.0, -5.0, 0.0), -down, 100.0), None);
+        assert_eq!(world.cast_ray(Point3::new(5.0, 7.0, 0.0), down, 100.0), None);
+    }
+
+    // Everything recordable that a session might do, on a fresh world
+    fn record_session(world: &mut PhysicsWorld) {
+        world.set_deterministic(true);
+        world.add_ground();
+        world.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        world.set_kill_plane(Some(-5.0));
+        world.set_max_bodies(Some(6));
+        let cubes = (0..5)
+            .map(|i| world.add_cube(Vector3::new(i as f32 * 1.1 - 2.2, 3.0 + i as f32, 0.0), 1.0))
+            .collect::<Vec<_>>();
+        let plank = world.add_box(Vector3::new(0.0, 8.0, 0.0), Vector3::new(2.0, 0.1, 0.5));
+        world.set_restitution(cubes[0], 0.8);
+        world.set_linear_damping(cubes[1], 0.3);
+        world.set_angular_damping(cubes[1], 0.3);
+        world.set_mass(cubes[2], 4.0);
+        world.add_ball_joint(cubes[3], cubes[4], Vector3::new(0.5, 0.0, 0.0), Vector3::new(-0.5, 0.0, 0.0));
+        for _ in 0..30 {
+            world.step(1.0 / 60.0);
+        }
+        world.apply_impulse_at_point(cubes[0], Vector3::new(1.0, 2.0, 0.0), Vector3::new(-2.2, 3.5, 0.2));
+        world.explode(Vector3::new(0.0, 0.0, 0.0), 4.0, 6.0);
+        world.set_body_transform(plank, Vector3::new(1.0, 6.0, 1.0), Quaternion::from_axis_angle(Vector3::unit_y(), Deg(30.0)), true);
+        world.remove_body(cubes[1]);
+        // Evicts the oldest body
+        world.add_cube(Vector3::new(0.0, 12.0, 0.0), 1.0);
+        world.add_cube(Vector3::new(0.0, 14.0, 0.0), 1.0);
+        for _ in 0..60 {
+            world.step(1.0 / 60.0);
+        }
+    }
+
+    #[test]
+    fn replaying_a_recording_reproduces_it_exactly() {
+        let mut original = PhysicsWorld::new();
+        original.start_recording();
+        record_session(&mut original);
+        let commands = original.stop_recording().unwrap();
+
+        let mut replayed = PhysicsWorld::new();
+        replayed.replay(&commands);
+
+        let summary = |world: &PhysicsWorld| {
+            let mut bodies = world
+                .get_bodies()
+                .iter()
+                .map(|(handle, body)| (handle.into_raw_parts(), body.position, body.rotation))
+                .collect::<Vec<_>>();
+            bodies.sort_by_key(|(handle, _, _)| *handle);
+            bodies
+        };
+        let expected = summary(&original);
+        assert_eq!(expected.len(), 6);
+        // Bit for bit, not just close
+        assert_eq!(summary(&replayed), expected);
+    }
+
+    #[test]
+    fn recording_refuses_what_it_cannot_replay() {
+        let mut world = PhysicsWorld::new();
+        world.start_recording();
+        world.add_trimesh_static(&[Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 0.0, 1.0)], &[[0, 1, 2]]);
+        world.step(1.0 / 60.0);
+        assert!(world.stop_recording().is_err());
+
+        world.start_recording();
+        world.step(1.0 / 60.0);
+        assert_eq!(world.stop_recording().unwrap(), vec![Command::Step { delta_time: 1.0 / 60.0 }]);
+    }
+
+    #[test]
+    fn undrained_despawned_handles_stay_bounded() {
+        let mut world = PhysicsWorld::new();
+        // Everything spawns below the kill plane and is removed by the first step
+        world.set_kill_plane(Some(100.0));
+        for round in 0..3 {
+            for i in 0..MAX_PENDING_DESPAWNED / 2 + 1 {
+                world.add_cube(Vector3::new(i as f32 * 2.0, round as f32 * 2.0, 0.0), 1.0);
+            }
+            world.step(1.0 / 60.0);
+        }
+        assert!(world.get_bodies().is_empty());
+        assert_eq!(world.take_despawned().len(), MAX_PENDING_DESPAWNED);
+        assert!(world.take_despawned().is_empty());
+    }
+
+    #[test]
+    fn entering_a_sensor_is_not_a_collision() {
+        let mut world = PhysicsWorld::new();
+        world.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        let sensor = world.add_sensor(Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 2.0, 2.0));
+        let cube = world.add_cube(Vector3::new(0.0, 4.0, 0.0), 1.0);
+        // Falls about 2.8m, into the middle of the sensor
+        for _ in 0..45 {
+            world.step(1.0 / 60.0);
+        }
+        assert_eq!(world.bodies_in_sensor(sensor), vec![cube]);
+        assert!(world.take_collisions().is_empty());
+    }
+
+    #[test]
+    fn invalid_trimesh_is_skipped() {
+        let mut world = PhysicsWorld::new();
+        let triangle = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 0.0, 1.0)];
+        let colliders = world.collider_set.len();
+
+        assert_eq!(world.add_trimesh_static(&triangle, &[]), None);
+        assert_eq!(world.add_trimesh_static(&[], &[]), None);
+        assert_eq!(world.add_trimesh_static(&triangle, &[[0, 1, 3]]), None);
+        assert_eq!(world.collider_set.len(), colliders);
+        // Nothing was added, so a recording isn't spoiled
+        world.start_recording();
+        assert_eq!(world.add_trimesh_static(&triangle, &[[0, 1, 5]]), None);
+        assert!(world.stop_recording().is_ok());
+
+        assert!(world.add_trimesh_static(&triangle, &[[0, 1, 2]]).is_some());
+        assert_eq!(world.collider_set.len(), colliders + 1);
+    }
 }
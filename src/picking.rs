@@ -0,0 +1,209 @@
+use std::sync::{Arc, Mutex};
+
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::texture::Texture;
+
+// Format of the id target the picking pass renders into
+pub const PICKING_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// Copies out of a texture need rows padded to this alignment, even for a single texel
+const READBACK_SIZE: wgpu::BufferAddress = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
+
+/// A finished GPU pick, see `Renderer::request_pick`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickResult {
+    pub x: u32,
+    pub y: u32,
+    /// The body drawn at that pixel, None for background or the ground
+    pub body: Option<RigidBodyHandle>,
+}
+
+// A pick whose texel was copied into the readback buffer
+struct InFlight {
+    x: u32,
+    y: u32,
+    handles: Vec<Option<RigidBodyHandle>>, // Body behind each id when the pick was drawn
+}
+
+/// Finds the body under a pixel by drawing instance ids into an offscreen target and reading one texel back
+/// Nothing waits on the GPU, a pick is drawn with the next frame and read back a frame or two later
+pub struct Picker {
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_texture: Texture,
+    readback_buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    requested: Option<(u32, u32)>, // Drawn with the next frame once no readback is pending
+    copied: Option<InFlight>, // Copied this frame, mapped after submit
+    in_flight: Option<InFlight>, // readback_buffer is mapped or being mapped, don't copy into it
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>, // Set by the map_async callback
+    finished: Option<PickResult>,
+}
+
+impl Picker {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, depth_format: wgpu::TextureFormat) -> Self {
+        let (id_texture, id_view) = create_id_texture(device, config);
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Readback Buffer"),
+            size: READBACK_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            id_texture,
+            id_view,
+            depth_texture: Texture::create_depth_texture(device, config, depth_format, "picking_depth_texture"),
+            readback_buffer,
+            width: config.width,
+            height: config.height,
+            requested: None,
+            copied: None,
+            in_flight: None,
+            mapped: Arc::new(Mutex::new(None)),
+            finished: None,
+        }
+    }
+
+    /// Recreate the targets at the surface's new size, a pending readback isn't affected
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, depth_format: wgpu::TextureFormat) {
+        self.id_texture.destroy();
+        self.depth_texture.texture.destroy();
+        (self.id_texture, self.id_view) = create_id_texture(device, config);
+        self.depth_texture = Texture::create_depth_texture(device, config, depth_format, "picking_depth_texture");
+        self.width = config.width;
+        self.height = config.height;
+    }
+
+    /// Look up surface pixel (`x`, `y`) with the next frame, replacing a request that hasn't been drawn yet
+    pub fn request(&mut self, x: u32, y: u32) {
+        if x >= self.width || y >= self.height {
+            self.finished = Some(PickResult { x, y, body: None });
+            return;
+        }
+        self.requested = Some((x, y));
+    }
+
+    /// Whether a picking pass should be drawn this frame
+    pub fn wants_pass(&self) -> bool {
+        self.requested.is_some() && self.in_flight.is_none()
+    }
+
+    /// Start the picking pass, every pixel starts out as id 0 (nothing)
+    pub fn begin_pass<'e>(&self, encoder: &'e mut wgpu::CommandEncoder) -> wgpu::RenderPass<'e> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Copy the requested texel for reading, call after the pass from `begin_pass` ends
+    /// `handles` are the bodies behind each id in the pass, id 1 being the first
+    pub fn copy(&mut self, encoder: &mut wgpu::CommandEncoder, handles: Vec<Option<RigidBodyHandle>>) {
+        let Some((x, y)) = self.requested.take() else {
+            return;
+        };
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        );
+        self.copied = Some(InFlight { x, y, handles });
+    }
+
+    /// Start reading back what `copy` copied, call after the encoder is submitted
+    pub fn after_submit(&mut self) {
+        let Some(copied) = self.copied.take() else {
+            return;
+        };
+        self.in_flight = Some(copied);
+        let mapped = self.mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            *mapped.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+        });
+    }
+
+    /// The last requested pick once it has been read back, without waiting for it
+    pub fn take_result(&mut self, device: &wgpu::Device) -> Option<PickResult> {
+        if let Some(result) = self.finished.take() {
+            return Some(result);
+        }
+        self.in_flight.as_ref()?;
+        // Native backends only run map callbacks while polled, this returns straight away
+        let _ = device.poll(wgpu::PollType::Poll);
+        let mapped = self.mapped.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+        let in_flight = self.in_flight.take()?;
+
+        let body = match mapped {
+            Ok(()) => {
+                let id = {
+                    let data = self.readback_buffer.slice(..).get_mapped_range();
+                    u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+                };
+                self.readback_buffer.unmap();
+                id.checked_sub(1).and_then(|index| in_flight.handles.get(index as usize).copied().flatten())
+            }
+            Err(e) => {
+                log::warn!("Reading back a pick failed: {}", e);
+                None
+            }
+        };
+        Some(PickResult { x: in_flight.x, y: in_flight.y, body })
+    }
+
+    pub fn destroy(&self) {
+        self.id_texture.destroy();
+        self.depth_texture.texture.destroy();
+        self.readback_buffer.destroy();
+    }
+}
+
+fn create_id_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Picking Texture"),
+        size: wgpu::Extent3d {
+            width: config.width.max(1),
+            height: config.height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: PICKING_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
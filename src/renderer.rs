@@ -1,15 +1,31 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{EuclideanSpace, InnerSpace};
+use web_time::Instant;
 use wgpu::util::DeviceExt;
 use winit::{
-    event::*, event_loop::ActiveEventLoop, keyboard::KeyCode, window::Window
+    dpi::{LogicalSize, PhysicalPosition}, event::*, event_loop::ActiveEventLoop, keyboard::KeyCode, window::{Window, WindowAttributes}
 };
 
 
-use crate::camera::{CameraSystem, Instance};
+use crate::camera::{CameraSystem, Frustum, Instance, OPENGL_TO_WGPU_MATRIX};
+use crate::debug::{DebugLines, DebugVertex, Overlay};
+use crate::fog::Fog;
+use crate::light::{Light, LightSystem};
+use crate::text::TextRenderer;
 use crate::texture::Texture;
-use crate::model::{Model, ModelVertex, DrawModel, Vertex as ModelVertexTrait};
-use crate::resources;
-use crate::physics::PhysicsWorld;
+use crate::timing::GpuTimer;
+use crate::geometry;
+use crate::particles::ParticleSystem;
+use crate::picking::{PICKING_FORMAT, PickResult, Picker};
+use crate::model::{Material, Mesh, Model, ModelVertex, DrawModel, Vertex as ModelVertexTrait};
+use crate::resources::{self, ResourceManager};
+use crate::input::{Action, KeyBindings};
+use crate::physics::{BodySummary, PhysicsWorld};
+use crate::scene::Scene;
+use crate::sim::SimLoop;
 use rapier3d::prelude::RigidBodyHandle;
 
 
@@ -20,14 +36,32 @@ use rapier3d::prelude::RigidBodyHandle;
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
     model: [[f32; 4]; 4],
+    alpha: f32,
+    id: u32, // Picking id, index into the visible instances plus one so 0 means nothing was hit
+    color: [f32; 3],
 }
 
 impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
+    fn to_raw(&self, id: u32) -> InstanceRaw {
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+            model: (cgmath::Matrix4::from_translation(self.position)
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
+            .into(),
+            alpha: self.alpha,
+            id,
+            color: self.color,
         }
     }
+
+    /// Radius of a sphere around the instance that contains the whole scaled model
+    fn bounding_radius(&self) -> f32 {
+        INSTANCE_BOUNDING_RADIUS * self.scale.x.abs().max(self.scale.y.abs()).max(self.scale.z.abs())
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.alpha < 1.0
+    }
 }
 
 impl InstanceRaw {
@@ -64,86 +98,865 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 17]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
 
-// Constants for instancing
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const SPACE_BETWEEN: f32 = 5.0;
+// Layout of a material's texture and sampler, group 1 of the model pipeline
+fn create_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            // Texture
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            }
+        ],
+        label: Some("texture_bind_group_layout"),
+    })
+}
 
-// This will store the state of our game
-pub struct State {
-    surface: wgpu::Surface<'static>,
+// Draw `model` for each of `instances` in `instance_buffer`, an empty range binds and draws nothing
+// Slicing a zero-sized buffer panics in wgpu, which is what a checkerboard without tiles would hand us
+fn draw_instances<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    instance_buffer: &'a wgpu::Buffer,
+    instances: std::ops::Range<u32>,
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    if instances.is_empty() {
+        return;
+    }
+    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+    render_pass.draw_model_instanced(model, instances, camera_bind_group);
+}
+
+// Number of instances the instance buffer starts out with room for
+const INITIAL_INSTANCE_CAPACITY: usize = 16;
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Capacity to reallocate the instance buffer with so it holds `len` instances, None if it already fits
+/// The buffer never shrinks, draws only use the first `len` instances so anything past that is ignored
+fn grown_instance_capacity(capacity: usize, len: usize, max_instances: usize) -> Option<usize> {
+    if len <= capacity {
+        return None;
+    }
+    // Grow enough that this stays rare
+    let mut grown = capacity.max(1);
+    while grown < len {
+        grown *= 2;
+    }
+    Some(grown.min(max_instances))
+}
+
+// Height of the body labels and the stats readout in logical pixels, scaled up on HiDPI screens
+const LABEL_HEIGHT: f32 = 14.0;
+// Gap between the stats readout and the top left corner of the window, in logical pixels
+const STATS_MARGIN: f32 = 8.0;
+// Length of the debug normal lines relative to the body's smallest side
+const NORMAL_LINE_LENGTH: f32 = 0.3;
+
+/// Maps surface pixels (y pointing down) to clip space, for drawing overlays in screen space
+fn screen_space_matrix(width: u32, height: u32) -> [[f32; 4]; 4] {
+    let ortho = cgmath::ortho(0.0, width.max(1) as f32, height.max(1) as f32, 0.0, -1.0, 1.0);
+    (OPENGL_TO_WGPU_MATRIX * ortho).into()
+}
+
+// Radius of a sphere enclosing the unit cube model, used for culling before scaling
+const INSTANCE_BOUNDING_RADIUS: f32 = 0.87;
+
+/// How colors written by the fragment shader end up on screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The surface encodes to sRGB for us
+    Srgb,
+    /// The surface stores values as-is, so the shader applies gamma correction itself
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn from_format(format: wgpu::TextureFormat) -> Self {
+        if format.is_srgb() {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        }
+    }
+}
+
+/// Settings that differ between the variants of the model pipeline
+struct PipelineVariant {
+    label: &'static str,
+    blend: wgpu::BlendState,
+    depth_write_enabled: bool,
+    cull_mode: Option<wgpu::Face>,
+}
+
+impl PipelineVariant {
+    const OPAQUE: Self = Self {
+        label: "Render Pipeline",
+        blend: wgpu::BlendState::REPLACE,
+        depth_write_enabled: true,
+        cull_mode: Some(wgpu::Face::Back),
+    };
+
+    // Draws both sides of every triangle, handy for spotting inside-out faces in loaded models
+    const OPAQUE_NO_CULL: Self = Self {
+        label: "Render Pipeline (No Culling)",
+        cull_mode: None,
+        ..Self::OPAQUE
+    };
+
+    // Back faces stay visible through translucent bodies
+    const TRANSPARENT: Self = Self {
+        label: "Transparent Render Pipeline",
+        blend: wgpu::BlendState::ALPHA_BLENDING,
+        depth_write_enabled: false,
+        cull_mode: None,
+    };
+}
+
+fn create_model_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    color_space: ColorSpace,
+    variant: &PipelineVariant,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(variant.label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[
+                ModelVertex::desc(),
+                InstanceRaw::desc(),
+            ],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState { // 3.
+            module: shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState { // 4.
+                format,
+                blend: Some(variant.blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[("apply_gamma", if color_space == ColorSpace::Linear { 1.0 } else { 0.0 })],
+                ..Default::default()
+            },
+        }),
+        //this field describes how to interpret the vertices when converting them to triangles
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList, // 1. every three vertices will become a triangle
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw, // 2. tells when a triangle is facing forward: orientation of the vertices are counter clockwise
+            cull_mode: variant.cull_mode,
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: variant.depth_write_enabled,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1, // 2.
+            mask: !0, // 3.
+            alpha_to_coverage_enabled: false, // 4. for anti aliasing
+        },
+        multiview: None, // 5.
+        cache: None, // 6.
+    })
+}
+
+// Format of the texture a headless renderer draws into, sRGB like the surfaces we prefer
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Nothing is presented offscreen, so vsync is all there is to report
+const OFFSCREEN_PRESENT_MODES: &[wgpu::PresentMode] = &[wgpu::PresentMode::Fifo];
+
+// Where frames are drawn: the window's surface, or a texture when there's no window
+enum RenderTarget {
+    Surface(wgpu::Surface<'static>),
+    Offscreen(wgpu::Texture),
+}
+
+fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Target"),
+        size: wgpu::Extent3d { width: config.width, height: config.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        // Copied out to read the frame back
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        #[cfg(not(target_arch = "wasm32"))]
+        backends: wgpu::Backends::PRIMARY,
+        #[cfg(target_arch = "wasm32")]
+        backends: wgpu::Backends::GL,
+        ..Default::default()
+    })
+}
+
+/// An adapter that can draw to `surface`, or any adapter without one
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    power_preference: wgpu::PowerPreference,
+    surface: Option<&wgpu::Surface<'static>>,
+) -> anyhow::Result<wgpu::Adapter> {
+    let adapter = match instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference,
+            compatible_surface: surface,
+            force_fallback_adapter: false,
+        })
+        .await
+    {
+        Ok(adapter) => adapter,
+        Err(e) => {
+            // No usable GPU (e.g. headless CI), try a software adapter before giving up
+            log::warn!("No GPU adapter found ({}), trying the fallback adapter", e);
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference,
+                    compatible_surface: surface,
+                    force_fallback_adapter: true,
+                })
+                .await
+                .context("requesting a GPU adapter")?
+        }
+    };
+    let adapter_info = adapter.get_info();
+    log::info!("Using {} ({:?}, {:?} backend)", adapter_info.name, adapter_info.device_type, adapter_info.backend);
+    Ok(adapter)
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            // Timestamp queries are only used for last_gpu_frame_ms, so they're requested when available
+            required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+            // WebGL doesn't support all of wgpu's features, so if
+            // we're building for the web we'll have to disable some.
+            required_limits: if cfg!(target_arch = "wasm32") {
+                wgpu::Limits::downlevel_webgl2_defaults()
+            } else {
+                wgpu::Limits::default()
+            },
+            memory_hints: Default::default(),
+            trace: wgpu::Trace::Off,
+        })
+        .await
+        .context("requesting a GPU device")
+}
+
+/// Why `render` didn't draw a frame, split by what the caller should do about it
+#[derive(Debug)]
+pub enum RenderError {
+    /// The surface is lost or outdated, reconfigure it (e.g. `resize` with the window size) and carry on
+    Reconfigure(wgpu::SurfaceError),
+    /// A transient failure like a timeout, just skip this frame
+    Skip(wgpu::SurfaceError),
+    /// Rendering can't continue, e.g. the GPU is out of memory
+    Fatal(wgpu::SurfaceError),
+}
+
+impl RenderError {
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, RenderError::Fatal(_))
+    }
+}
+
+impl From<wgpu::SurfaceError> for RenderError {
+    fn from(error: wgpu::SurfaceError) -> Self {
+        match error {
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => RenderError::Reconfigure(error),
+            wgpu::SurfaceError::OutOfMemory => RenderError::Fatal(error),
+            _ => RenderError::Skip(error),
+        }
+    }
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::Reconfigure(error) => write!(f, "surface needs reconfiguring: {}", error),
+            RenderError::Skip(error) => write!(f, "frame skipped: {}", error),
+            RenderError::Fatal(error) => write!(f, "fatal surface error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// Whether presentation waits for vblank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// Cap the frame rate to the display refresh rate, saves power
+    #[default]
+    Vsync,
+    /// Present as fast as possible, useful for benchmarking
+    Uncapped,
+}
+
+impl PresentMode {
+    /// The wgpu present mode to use given what the surface supports
+    /// Uncapped prefers Mailbox (no tearing) then Immediate, anything unsupported falls back to Fifo
+    fn resolve(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let preferred: &[wgpu::PresentMode] = match self {
+            PresentMode::Vsync => &[wgpu::PresentMode::Fifo],
+            PresentMode::Uncapped => &[wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate],
+        };
+        preferred
+            .iter()
+            .copied()
+            .find(|mode| supported.contains(mode))
+            // Fifo is the one mode every surface has to support
+            .unwrap_or(wgpu::PresentMode::Fifo)
+    }
+}
+
+/// Pipeline for the picking pass, which writes instance ids instead of colors
+fn create_picking_pipeline(
+    device: &wgpu::Device,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    depth_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Picking Pipeline Layout"),
+        bind_group_layouts: &[camera_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Picking Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_pick"),
+            buffers: &[
+                ModelVertex::desc(),
+                InstanceRaw::desc(),
+            ],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_pick"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: PICKING_FORMAT,
+                blend: None, // Integer targets can't blend
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// A checkerboard of flat tiles drawn on the ground so distances and motion are easy to read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkerboard {
+    pub tiles: u32, // Tiles along each side
+    pub tile_size: f32,
+    pub y: f32, // Height of the tiles, normally the ground's top surface
+    pub colors: [[f32; 3]; 2], // Alternating tile tints
+}
+
+impl Default for Checkerboard {
+    fn default() -> Self {
+        Self {
+            tiles: 40,
+            tile_size: 2.0,
+            y: 0.0,
+            colors: [[0.8, 0.8, 0.8], [0.35, 0.35, 0.35]],
+        }
+    }
+}
+
+impl Checkerboard {
+    /// One instance per tile, centered on the origin, with neighbouring tiles in different colors
+    pub fn instances(&self) -> Vec<Instance> {
+        let offset = self.tiles.saturating_sub(1) as f32 * self.tile_size / 2.0;
+        let mut instances = Vec::with_capacity((self.tiles * self.tiles) as usize);
+        for z in 0..self.tiles {
+            for x in 0..self.tiles {
+                let position = cgmath::Vector3::new(
+                    x as f32 * self.tile_size - offset,
+                    self.y,
+                    z as f32 * self.tile_size - offset,
+                );
+                instances.push(Instance {
+                    color: self.colors[((x + z) % 2) as usize],
+                    ..Instance::new(position, cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0))
+                });
+            }
+        }
+        instances
+    }
+}
+
+/// Read an image from disk, or fetch it relative to the page on the web
+async fn load_texture_at(device: &wgpu::Device, queue: &wgpu::Queue, path: &str) -> anyhow::Result<Texture> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let texture = Texture::from_path(device, queue, std::path::Path::new(path), path);
+    #[cfg(target_arch = "wasm32")]
+    let texture = Texture::from_url(device, queue, path, path).await;
+    texture.with_context(|| format!("loading texture {}", path))
+}
+
+/// Configures how a `State` is created
+/// `State::new` uses the defaults, which reproduce the built-in demo scene
+#[derive(Clone)]
+pub struct StateBuilder {
+    clear_color: wgpu::Color,
+    camera_eye: Option<cgmath::Point3<f32>>,
+    camera_target: Option<cgmath::Point3<f32>>,
+    default_grid: bool,
+    grid_rows: u32,
+    grid_cols: u32,
+    grid_spacing: f32,
+    grid_drop_height: f32,
+    texture_path: Option<String>,
+    ground_texture_path: Option<String>,
+    max_resolution: Option<u32>,
+    present_mode: PresentMode,
+    checkerboard: Option<Checkerboard>,
+    shader_source: Option<String>,
+    power_preference: wgpu::PowerPreference,
+    persist_camera: bool,
+    window_title: String,
+    window_size: Option<LogicalSize<f64>>,
+    resizable: bool,
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        Self {
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            camera_eye: None,
+            camera_target: None,
+            default_grid: true,
+            grid_rows: 2,
+            grid_cols: 2,
+            grid_spacing: 2.0,
+            grid_drop_height: 0.0,
+            texture_path: None,
+            ground_texture_path: None,
+            max_resolution: None,
+            present_mode: PresentMode::Vsync,
+            checkerboard: Some(Checkerboard::default()),
+            shader_source: None,
+            power_preference: wgpu::PowerPreference::default(),
+            persist_camera: false,
+            window_title: "Physics Renderer".to_string(),
+            window_size: None,
+            resizable: true,
+        }
+    }
+}
+
+impl StateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Color the frame is cleared to before drawing
+    pub fn with_clear_color(mut self, color: wgpu::Color) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    /// Initial camera position and look-at point
+    /// Without this the camera is placed to frame the spawned cubes
+    pub fn with_camera(mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) -> Self {
+        self.camera_eye = Some(eye);
+        self.camera_target = Some(target);
+        self
+    }
+
+    /// Whether to spawn the demo grid of cubes on startup
+    pub fn with_default_grid(mut self, enabled: bool) -> Self {
+        self.default_grid = enabled;
+        self
+    }
+
+    /// Layout of the startup grid: `rows` x `cols` cubes `spacing` apart, dropped from `drop_height`
+    /// The camera frames the whole grid unless `with_camera` is also used
+    pub fn with_grid(mut self, rows: u32, cols: u32, spacing: f32, drop_height: f32) -> Self {
+        self.grid_rows = rows;
+        self.grid_cols = cols;
+        self.grid_spacing = spacing;
+        self.grid_drop_height = drop_height;
+        self
+    }
+
+    /// Diffuse texture for the cubes, read from disk at runtime (fetched relative to the page on wasm)
+    /// Without this the bundled `assets/texture.jpg` is used
+    pub fn with_texture_path(mut self, path: &str) -> Self {
+        self.texture_path = Some(path.to_string());
+        self
+    }
+
+    /// Texture for the ground tiles, loaded the same way as `with_texture_path`
+    /// It covers each checkerboard tile once and is tinted by the tile colors, without it the tiles are plain colors
+    pub fn with_ground_texture_path(mut self, path: &str) -> Self {
+        self.ground_texture_path = Some(path.to_string());
+        self
+    }
+
+    /// Cap on the surface width and height in physical pixels, `None` for no cap
+    /// On HiDPI screens a low cap renders below the display's resolution and looks blurry
+    pub fn with_max_resolution(mut self, max_resolution: Option<u32>) -> Self {
+        self.max_resolution = max_resolution;
+        self
+    }
+
+    /// Vsync or uncapped presentation, falls back to vsync if the surface can't do uncapped
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Tiles drawn on the ground, `None` leaves the ground invisible
+    pub fn with_checkerboard(mut self, checkerboard: Option<Checkerboard>) -> Self {
+        self.checkerboard = checkerboard;
+        self
+    }
+
+    /// WGSL source for drawing bodies and the ground instead of the bundled `shader.wgsl`
+    /// It needs the same `vs_main`/`fs_main` entry points, bindings and `apply_gamma` override,
+    /// copying `shader.wgsl` is the easiest start. Errors in it are returned from `build`
+    pub fn with_shader_source(mut self, source: &str) -> Self {
+        self.shader_source = Some(source.to_string());
+        self
+    }
+
+    /// Whether to ask for the fast GPU or the battery friendly one when there are several
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    /// Whether to restore the camera from the last session and save it as it moves (off by default)
+    /// It's kept in localStorage on the web and in the user's config directory natively, see `resources::save_setting`
+    /// The restored camera replaces the one framed around the scene, a camera set with `with_camera` takes precedence
+    pub fn with_camera_persistence(mut self, enabled: bool) -> Self {
+        self.persist_camera = enabled;
+        self
+    }
+
+    /// Title of the window. On the web it becomes the page title
+    pub fn with_window_title(mut self, title: impl Into<String>) -> Self {
+        self.window_title = title.into();
+        self
+    }
+
+    /// Initial window size in logical pixels, the platform picks one without this
+    /// Ignored on the web, where the canvas keeps the size the page lays it out at
+    pub fn with_window_size(mut self, width: f64, height: f64) -> Self {
+        self.window_size = Some(LogicalSize::new(width, height));
+        self
+    }
+
+    /// Whether the user can resize the window (default true), ignored on the web
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Attributes for the window `build` is given, with the title, size and resizability set here
+    pub fn window_attributes(&self) -> WindowAttributes {
+        let mut attributes = Window::default_attributes()
+            .with_title(self.window_title.clone())
+            .with_resizable(self.resizable);
+        if let Some(size) = self.window_size {
+            attributes = attributes.with_inner_size(size);
+        }
+        attributes
+    }
+
+    pub async fn build(self, window: Arc<Window>) -> anyhow::Result<State> {
+        State::from_builder(window, self).await
+    }
+}
+
+// Spring pulling a grabbed body towards the cursor, in 1/s^2, and its damping in 1/s
+// The damping is 2 * sqrt(stiffness) so the body settles on the cursor without overshooting
+const GRAB_STIFFNESS: f32 = 100.0;
+const GRAB_DAMPING: f32 = 20.0;
+// Longest frame the grab spring integrates over at once, it's only stable while dt * GRAB_DAMPING < 2
+const GRAB_MAX_DT: f32 = 0.05;
+// Farthest a body under the mouse can be for pick_at_cursor and grabbing
+const PICK_DISTANCE: f32 = 1000.0;
+
+/// A body being dragged with the mouse
+#[derive(Debug, Clone, Copy)]
+struct Grab {
+    handle: RigidBodyHandle,
+    // The body is pulled around on this plane, through where it was grabbed and facing the camera
+    plane_point: cgmath::Point3<f32>,
+    plane_normal: cgmath::Vector3<f32>,
+}
+
+// Contact impulse (N*s) that throws the most sparks, about a 1kg cube hitting something at 10 m/s
+const FULL_SPARKS_IMPULSE: f32 = 10.0;
+// Impacts at least this hard shake the camera
+const SHAKE_IMPULSE: f32 = 5.0;
+// Camera shake for the hardest impacts, in world units and seconds, see State::set_impact_shake
+const DEFAULT_SHAKE_INTENSITY: f32 = 0.15;
+const DEFAULT_SHAKE_DURATION: f32 = 0.3;
+
+// Reach (m) and impulse at the center (N*s) of the explode key's blast
+const EXPLOSION_RADIUS: f32 = 5.0;
+const EXPLOSION_STRENGTH: f32 = 12.0;
+
+// Name the camera is saved under, see StateBuilder::with_camera_persistence
+const CAMERA_SETTING: &str = "physicsrenderer_camera";
+// Seconds between saves while the camera keeps moving
+const CAMERA_SAVE_INTERVAL: f32 = 1.0;
+
+// Damping the "freeze" key applies, high enough that bodies coast to a stop within a second or so
+const FREEZE_DAMPING: f32 = 5.0;
+
+// Default height above the ground cubes dropped with the mouse start at
+const DEFAULT_SPAWN_HEIGHT: f32 = 5.0;
+// How far in front of the camera shot cubes appear, and how fast they leave
+const SHOOT_DISTANCE: f32 = 2.0;
+const SHOOT_SPEED: f32 = 15.0;
+
+// Number of frames the FPS readout averages over
+const FRAME_STATS_WINDOW: usize = 60;
+// Seconds between FPS readout refreshes, so the number is readable
+const FRAME_STATS_REFRESH: f32 = 0.5;
+
+/// Rolling frame timing for the FPS readout
+#[derive(Default)]
+struct FrameStats {
+    frame_times: VecDeque<f32>,
+    total_frame_time: f32,
+    physics_steps_last_frame: u32,
+    since_refresh: f32,
+}
+
+impl FrameStats {
+    /// Record a frame, returns true when it's time to refresh the readout
+    fn record(&mut self, frame_time: f32, physics_steps: u32) -> bool {
+        self.frame_times.push_back(frame_time);
+        self.total_frame_time += frame_time;
+        if self.frame_times.len() > FRAME_STATS_WINDOW {
+            self.total_frame_time -= self.frame_times.pop_front().unwrap_or(0.0);
+        }
+        self.physics_steps_last_frame = physics_steps;
+
+        self.since_refresh += frame_time;
+        if self.since_refresh >= FRAME_STATS_REFRESH {
+            self.since_refresh = 0.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            0.0
+        } else {
+            self.total_frame_time / self.frame_times.len() as f32
+        }
+    }
+
+    fn fps(&self) -> f32 {
+        let average = self.average_frame_time();
+        if average > 0.0 { 1.0 / average } else { 0.0 }
+    }
+}
+
+/// Owns all GPU state and draws whatever `PhysicsWorld` it is handed
+/// It never holds the physics world itself, so the simulation can be driven from anywhere
+pub struct Renderer {
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     is_surface_configured: bool,
     render_pipeline: wgpu::RenderPipeline,
+    no_cull_pipeline: wgpu::RenderPipeline, // Used for opaque instances when backface_culling is off
+    backface_culling: bool,
+    transparent_pipeline: wgpu::RenderPipeline,
     instances: Vec<Instance>,
+    // Instances that survived culling and were uploaded, ordered opaque first
+    visible_instance_count: usize,
+    // Where the transparent instances start in the uploaded buffer
+    opaque_instance_count: usize,
+    frustum_culling: bool,
     instance_buffer: wgpu::Buffer,
-    obj_model: Model,
+    instance_capacity: usize, // How many instances instance_buffer can hold
+    instance_data: Vec<InstanceRaw>, // Staging for the upload, kept to avoid reallocating every frame
+    visible_handles: Vec<Option<RigidBodyHandle>>, // Body behind each uploaded instance, in upload order
+    instance_limit_warned: bool, // Already logged that there are more instances than the device can draw
+    obj_model: Arc<Model>,
+    resource_manager: ResourceManager,
     camera_system: CameraSystem,
-    diffuse_bind_group: wgpu::BindGroup,
+    light_system: LightSystem,
+    fog: Fog,
     diffuse_texture: Texture,
     depth_texture: Texture,
+    depth_format: wgpu::TextureFormat, // Shared by depth_texture and both pipelines
+    window: Option<Arc<Window>>, // None when drawing offscreen
+    clear_color: wgpu::Color,
+    max_resolution: Option<u32>,
+    present_mode: PresentMode,
+    supported_present_modes: Vec<wgpu::PresentMode>,
+    color_space: ColorSpace,
+    debug_lines: DebugLines,
+    show_colliders: bool, // Draw collider outlines over the scene
+    // Body handle labels, drawn in screen space with their own pixel-space camera
+    labels: TextRenderer,
+    show_labels: bool,
+    stats: TextRenderer, // Frame rate readout in the top left corner, see set_stats_text
+    show_stats: bool,
+    normal_lines: DebugLines,
+    show_normals: bool, // Draw each body's vertex normals, to check loaded or computed normals
+    screen_uniform_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    picking_pipeline: wgpu::RenderPipeline,
+    picker: Picker, // Target and readback for request_pick
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // Checkerboard tiles, drawn with their own plane mesh and instance buffer
+    checkerboard: Option<Checkerboard>,
+    ground_texture: Option<Texture>, // Sampled on every tile, None for plain tile colors
+    ground_model: Option<Model>,
+    ground_instance_buffer: Option<wgpu::Buffer>,
+    ground_instance_count: u32,
+    particles: Option<ParticleSystem>, // None when the adapter can't run compute shaders
+    gpu_timer: Option<GpuTimer>, // None without timestamp query support
+    overlay: Overlay,
+    overlay_screen_space: bool, // Overlay positions are in pixels rather than world units
+    destroyed: bool, // destroy was called, so Drop has nothing to warn about
+}
+
+// This will store the state of our game
+/// Bundles a `Renderer` with the `PhysicsWorld` it draws, for the demo app
+pub struct State {
+    renderer: Renderer,
     pub window: Arc<Window>,
-    physics_world: PhysicsWorld,
+    sim: SimLoop, // Owns the physics world and steps it at a fixed rate
     physics_bodies: Vec<RigidBodyHandle>, // Store handles to physics bodies
+    last_update: Instant, // When update last ran, for measuring frame time
+    key_bindings: KeyBindings,
+    frame_stats: FrameStats,
+    cursor_position: Option<PhysicalPosition<f64>>, // Last known mouse position in window pixels
+    instances_dirty: bool, // The world was changed from outside, rebuild instances even if everything sleeps
+    spawn_height: f32, // Height cubes dropped with the mouse start at
+    spawn_size: f32, // Side length of cubes spawned interactively
+    grab: Option<Grab>, // Body being dragged with the mouse
+    shake_intensity: f32, // Camera shake for hard impacts, 0 turns it off
+    shake_duration: f32,
+    persist_camera: bool,
+    camera_save_pending: bool, // The camera moved since it was last saved
+    last_camera_save: Instant,
 }
 
-impl State {
+impl Renderer {
     // Camera positioning methods moved to CameraSystem in camera.rs
 
     // Add this method to position camera looking at instances center
-    fn position_camera_at_instances_center(&mut self) {
+    pub fn position_camera_at_instances_center(&mut self) {
         self.camera_system.position_camera_at_instances_center(&self.instances, &self.queue);
     }
 
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+    /// Create all GPU resources for `window` using the rendering options in `builder`
+    pub async fn new(window: Arc<Window>, builder: &StateBuilder) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
-        // Camera system will be created later in the initialization
-
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            ..Default::default()
-        });
-
-        let surface = instance.create_surface(window.clone()).unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await?;
-        
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                // WebGL doesn't support all of wgpu's features, so if
-                // we're building for the web we'll have to disable some.
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-            })
-            .await?;
+        let instance = create_instance();
+        let surface = instance
+            .create_surface(window.clone())
+            .context("creating the window surface")?;
+        let adapter = request_adapter(&instance, builder.power_preference, Some(&surface)).await?;
+        let (device, queue) = request_device(&adapter).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
+        // Prefer an sRGB surface texture. If the backend only offers linear formats the
+        // fragment shader gamma corrects its output instead, otherwise colors come out darker.
         let surface_format = surface_caps.formats.iter()
             .find(|f| f.is_srgb())
             .copied()
@@ -153,296 +966,711 @@ impl State {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: builder.present_mode.resolve(&surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
+        // Configure the surface initially
+        surface.configure(&device, &config);
+
+        let target = RenderTarget::Surface(surface);
+        Self::from_device(&adapter, device, queue, config, target, Some(window), builder).await
+    }
+
+    /// Create a renderer that draws into a `width` x `height` texture instead of a window, e.g. for tests
+    /// Frames end up in `offscreen_texture` after each `render`
+    pub async fn new_headless(width: u32, height: u32, builder: &StateBuilder) -> anyhow::Result<Self> {
+        // There's no surface to match, so any backend that can draw will do, e.g. software GL without a GPU
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = request_adapter(&instance, builder.power_preference, None).await?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: OFFSCREEN_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: builder.present_mode.resolve(OFFSCREEN_PRESENT_MODES),
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let target = RenderTarget::Offscreen(create_offscreen_texture(&device, &config));
+        Self::from_device(&adapter, device, queue, config, target, None, builder).await
+    }
+
+    // Everything after the device is the same whether frames go to a window or a texture
+    async fn from_device(
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        target: RenderTarget,
+        window: Option<Arc<Window>>,
+        builder: &StateBuilder,
+    ) -> anyhow::Result<Self> {
+        let color_space = ColorSpace::from_format(config.format);
+        let supported_present_modes = match &target {
+            RenderTarget::Surface(surface) => surface.get_capabilities(adapter).present_modes,
+            RenderTarget::Offscreen(_) => OFFSCREEN_PRESENT_MODES.to_vec(),
+        };
+
         // Load texture
-        let diffuse_bytes = include_bytes!("../assets/texture.jpg");
-        let diffuse_texture = Texture::from_bytes(&device, &queue, diffuse_bytes, "texture.jpg").unwrap();
+        let diffuse_texture = match &builder.texture_path {
+            Some(path) => load_texture_at(&device, &queue, path).await?,
+            // The bundled texture is only decoration, a plain white one will do if it's missing
+            None => match resources::load_default_texture(&device, &queue).await {
+                Ok(texture) => texture,
+                Err(e) => {
+                    log::warn!("Unable to load assets/texture.jpg, using plain white: {:#}", e);
+                    Texture::create_1x1_texture(&device, &queue, [255, 255, 255, 255], "default")
+                }
+            },
+        };
 
         // Create depth texture
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let depth_format = Texture::select_depth_format(adapter)?;
+        let depth_texture = Texture::create_depth_texture(&device, &config, depth_format, "depth_texture");
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        // A user shader can fail to parse or not match our pipelines, catch that as an error instead of a panic
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let bundled_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into())
         });
+        let shader = match &builder.shader_source {
+            Some(source) => device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Custom Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+            }),
+            None => bundled_shader.clone(),
+        };
 
         //TODO: change this so that the camera's initial target is towards the center of all instances (i.e. get the largest magnitude of x,y,z which would make an imaginery cube, and then set the camera to look at the center of that BUT ignore the z that comes out of this, and set the z an appropriate height above the ground)
         // Initialize camera system with default settings
         let camera_system = CameraSystem::new(&device);
+        let light_system = LightSystem::new(&device);
+        let fog = Fog::new(&device);
 
-        // Create texture bind group layout
-        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                // Texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                // Sampler
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                }
-            ],
-            label: Some("texture_bind_group_layout"),
-        });
-
-        // Create texture bind group
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
+        let texture_bind_group_layout = create_texture_bind_group_layout(&device);
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 camera_system.bind_group_layout(),
                 &texture_bind_group_layout,
+                light_system.bind_group_layout(),
+                fog.bind_group_layout(),
             ],
             push_constant_ranges: &[],
         });
         
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[
-                    ModelVertex::desc(),
-                    InstanceRaw::desc(),
-                ],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState { // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState { // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            //this field describes how to interpret the vertices when converting them to triangles
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1. every three vertices will become a triangle
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2. tells when a triangle is facing forward: orientation of the vertices are counter clockwise
-                cull_mode: None, // Disable face culling so all faces are visible
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1, // 2.
-                mask: !0, // 3.
-                alpha_to_coverage_enabled: false, // 4. for anti aliasing
-            },
-            multiview: None, // 5.
-            cache: None, // 6.
+        let render_pipeline = create_model_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            depth_format,
+            color_space,
+            &PipelineVariant::OPAQUE,
+        );
+        let no_cull_pipeline = create_model_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            depth_format,
+            color_space,
+            &PipelineVariant::OPAQUE_NO_CULL,
+        );
+        // Translucent bodies are drawn after the opaque ones, blended over them without writing depth
+        let transparent_pipeline = create_model_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            depth_format,
+            color_space,
+            &PipelineVariant::TRANSPARENT,
+        );
+        if let Some(error) = device.pop_error_scope().await {
+            if builder.shader_source.is_some() {
+                anyhow::bail!("custom shader is invalid: {}", error);
+            }
+            anyhow::bail!("failed to create render pipelines: {}", error);
+        }
+        // Picking always uses the bundled shader, custom shaders only change how things look
+        let picking_pipeline = create_picking_pipeline(
+            &device,
+            camera_system.bind_group_layout(),
+            &bundled_shader,
+            depth_format,
+        );
+        let picker = Picker::new(&device, &config, depth_format);
+        let debug_lines = DebugLines::new(
+            &device,
+            camera_system.bind_group_layout(),
+            config.format,
+            depth_format,
+            color_space,
+        );
+        let labels = TextRenderer::new(&device, camera_system.bind_group_layout(), config.format, color_space)?;
+        let stats = TextRenderer::new(&device, camera_system.bind_group_layout(), config.format, color_space)?;
+        let normal_lines = DebugLines::new(
+            &device,
+            camera_system.bind_group_layout(),
+            config.format,
+            depth_format,
+            color_space,
+        );
+        let screen_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Screen Space Buffer"),
+            contents: bytemuck::cast_slice(&[screen_space_matrix(config.width, config.height)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_system.bind_group_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("screen_space_bind_group"),
         });
 
-        // Load the cube model
-        let mut obj_model = resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        let overlay = Overlay::new(&device, camera_system.bind_group_layout(), config.format, color_space);
+
+        // Collision sparks need compute shaders, which WebGL doesn't have
+        let particles = if ParticleSystem::is_supported(adapter) {
+            Some(ParticleSystem::new(
+                &device,
+                camera_system.bind_group_layout(),
+                config.format,
+                depth_format,
+                color_space,
+            ))
+        } else {
+            log::info!("Compute shaders unsupported, collision sparks are disabled");
+            None
+        };
+
+        // Load the cube model, materials keep their own MTL textures and fall back to ours
+        // If the file can't be found (e.g. running outside the repo) draw a built-in cube instead
+        let mut resource_manager = ResourceManager::new();
+        let obj_model = match resource_manager.load_model(
+            "cube.obj",
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            Some(diffuse_texture.clone()),
+        )
             .await
-            .unwrap();
-        
-        // Update all materials to use our loaded texture
-        for material in &mut obj_model.materials {
-            material.diffuse_texture = Some(diffuse_texture.clone());
-            material.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("material_diffuse_bind_group"),
-            });
-        }
+        {
+            Ok(model) => model,
+            Err(e) => {
+                log::warn!("Unable to load cube.obj, using a built-in cube: {:#}", e);
+                let (vertices, indices) = geometry::cube(1.0);
+                Arc::new(Model {
+                    meshes: vec![Mesh::from_data(&device, "builtin_cube", vertices, indices, 0)],
+                    materials: vec![Material::from_texture(&device, &texture_bind_group_layout, "default", diffuse_texture.clone())],
+                })
+            }
+        };
 
         // Create instances based on physics bodies (initially empty)
         let instances = Vec::new();
 
-        // Create instance buffer (initially empty)
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice::<InstanceRaw, u8>(&[]), // Empty initially
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            }
-        );
+        // Create instance buffer with some room to start with, it grows as bodies are added
+        let instance_buffer = create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
 
-        // Initialize physics world
-        let mut physics_world = PhysicsWorld::new();
-        
-        // Add ground plane
-        physics_world.add_ground();
-        
-        // GUI: Add some physics cubes -> replace with gui functionality later to user can add these and create seperate file and functions for handling addition of objects via the gui
-        //GUI: modify this and have it as a button to add cubes, and under another panel that has a list of all the pbject, drop down for each cube and be able to modify its x,y,z and its rotations
-        let mut physics_bodies = Vec::new();
-        for z in 0..2 {
-            for x in 0..2 {
-                let position = cgmath::Vector3::new(
-                    x as f32 * 2.0 - 4.0,
-                    0.0, // Start above ground
-                    z as f32 * 2.0 - 4.0
-                );
-                let handle = physics_world.add_cube(position, 1.0);
-                physics_bodies.push(handle);
-            }
-        }
-
-        // Configure the surface initially
-        surface.configure(&device, &config);
-
-        // Create the state
-        let mut state = Self {
-            surface,
+        Ok(Self {
+            target,
             device,
             queue,
             config,
             is_surface_configured: true,
             render_pipeline,
+            no_cull_pipeline,
+            backface_culling: true,
+            transparent_pipeline,
             instances,
+            visible_instance_count: 0,
+            opaque_instance_count: 0,
+            frustum_culling: true,
             instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            instance_data: Vec::with_capacity(INITIAL_INSTANCE_CAPACITY),
+            visible_handles: Vec::with_capacity(INITIAL_INSTANCE_CAPACITY),
+            instance_limit_warned: false,
             obj_model,
+            resource_manager,
             camera_system,
-            diffuse_bind_group,
+            light_system,
+            fog,
             diffuse_texture,
             depth_texture,
+            depth_format,
             window,
-            physics_world,
-            physics_bodies,
+            clear_color: builder.clear_color,
+            max_resolution: builder.max_resolution,
+            present_mode: builder.present_mode,
+            supported_present_modes,
+            color_space,
+            debug_lines,
+            show_colliders: false,
+            labels,
+            show_labels: false,
+            stats,
+            show_stats: false,
+            normal_lines,
+            show_normals: false,
+            screen_uniform_buffer,
+            screen_bind_group,
+            picking_pipeline,
+            picker,
+            texture_bind_group_layout,
+            checkerboard: None,
+            ground_texture: None,
+            ground_model: None,
+            ground_instance_buffer: None,
+            ground_instance_count: 0,
+            particles,
+            gpu_timer,
+            overlay,
+            overlay_screen_space: false,
+            destroyed: false,
+        })
+    }
+
+    /// Cache of loaded models and textures, shared by everything this renderer draws
+    pub fn resource_manager(&mut self) -> &mut ResourceManager {
+        &mut self.resource_manager
+    }
+
+    /// Turn skipping of off-screen instances on or off, e.g. to rule it out when debugging
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.frustum_culling = enabled;
+    }
+
+    pub fn frustum_culling(&self) -> bool {
+        self.frustum_culling
+    }
+
+    /// Whether the shader is relying on the surface for sRGB encoding or doing it itself
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Switch between vsync and uncapped presentation, takes effect immediately
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        self.present_mode = present_mode;
+        if self.is_surface_configured {
+            self.reconfigure();
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    /// The present mode the surface is actually configured with, after any fallback to vsync
+    pub fn surface_present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Present modes the surface supports on this adapter
+    pub fn supported_present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.supported_present_modes
+    }
+
+    /// The texture a renderer from `new_headless` draws into, None when it draws to a window
+    /// It's recreated on resize, so look it up again after one
+    pub fn offscreen_texture(&self) -> Option<&wgpu::Texture> {
+        match &self.target {
+            RenderTarget::Surface(_) => None,
+            RenderTarget::Offscreen(texture) => Some(texture),
+        }
+    }
+
+    /// Format of the surface textures we render into
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    /// True if the surface encodes to sRGB itself, otherwise the shaders gamma correct (see `ColorSpace`)
+    pub fn is_srgb_surface(&self) -> bool {
+        self.config.format.is_srgb()
+    }
+
+    /// Add a point light, returns false if MAX_LIGHTS are already in the scene
+    /// Without any lights the scene is drawn fully lit
+    pub fn add_light(&mut self, light: Light) -> bool {
+        self.light_system.add_light(light, &self.queue)
+    }
+
+    pub fn clear_lights(&mut self) {
+        self.light_system.clear_lights(&self.queue);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        self.light_system.lights()
+    }
+
+    /// Fade distant surfaces towards `color` (linear RGB), thicker with higher `density`, 0 turns it off
+    /// Matching the clear color makes far away bodies dissolve into the background
+    pub fn set_fog(&mut self, color: [f32; 3], density: f32) {
+        self.fog.set(color, density, &self.queue);
+    }
+
+    /// Fog color and density, see `set_fog`
+    pub fn fog(&self) -> ([f32; 3], f32) {
+        (self.fog.color(), self.fog.density())
+    }
+
+    /// Skip triangles facing away from the camera, on by default since it's right for closed meshes
+    pub fn set_backface_culling(&mut self, enabled: bool) {
+        self.backface_culling = enabled;
+    }
+
+    pub fn backface_culling(&self) -> bool {
+        self.backface_culling
+    }
+
+    /// Draw collider outlines over the scene, they're refreshed on the next `sync_instances`
+    pub fn set_show_colliders(&mut self, show: bool) {
+        self.show_colliders = show;
+    }
+
+    pub fn show_colliders(&self) -> bool {
+        self.show_colliders
+    }
+
+    /// Draw a short line along each vertex normal of every body, refreshed on the next `sync_instances`
+    pub fn set_show_normals(&mut self, show: bool) {
+        self.show_normals = show;
+    }
+
+    pub fn show_normals(&self) -> bool {
+        self.show_normals
+    }
+
+    /// Draw each body's handle index above it, refreshed on the next `sync_instances`
+    pub fn set_show_labels(&mut self, show: bool) {
+        self.show_labels = show;
+    }
+
+    pub fn show_labels(&self) -> bool {
+        self.show_labels
+    }
+
+    /// Show a line of text in the top left corner, e.g. the frame rate, until it's replaced
+    pub fn set_stats_text(&mut self, text: &str) {
+        let margin = STATS_MARGIN * self.scale_factor();
+        self.stats.top_left((margin, margin), LABEL_HEIGHT * self.scale_factor(), text, [1.0, 1.0, 1.0, 1.0]);
+        self.stats.upload(&self.device, &self.queue);
+    }
+
+    pub fn set_show_stats(&mut self, show: bool) {
+        self.show_stats = show;
+    }
+
+    pub fn show_stats(&self) -> bool {
+        self.show_stats
+    }
+
+    // Physical pixels per logical pixel, 1 without a window
+    fn scale_factor(&self) -> f32 {
+        self.window.as_ref().map_or(1.0, |window| window.scale_factor() as f32)
+    }
+
+    /// Draw a checkerboard of tiles on the ground, or nothing with `None`
+    pub fn set_checkerboard(&mut self, checkerboard: Option<Checkerboard>) {
+        self.checkerboard = checkerboard;
+        self.ground_instance_count = 0;
+        let Some(checkerboard) = checkerboard else {
+            self.ground_model = None;
+            self.ground_instance_buffer = None;
+            return;
         };
 
-        // Update instances from physics bodies to get initial positions
-        state.update_instances_from_physics();
-        
-        // Position camera to look at the center of all instances
-        state.position_camera_at_instances_center();
+        let material = self.ground_material();
+        let (vertices, indices) = geometry::plane(checkerboard.tile_size);
+        let mesh = Mesh::from_data(&self.device, "ground_tile", vertices, indices, 0);
+        self.ground_model = Some(Model { meshes: vec![mesh], materials: vec![material] });
 
-        Ok(state)
+        let instance_data = checkerboard
+            .instances()
+            .iter()
+            .map(|instance| instance.to_raw(0)) // Id 0 so picking ignores the ground
+            .collect::<Vec<_>>();
+        self.ground_instance_buffer = Some(self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ground Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        }));
+        self.ground_instance_count = instance_data.len() as u32;
     }
 
-    pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        match (code, is_pressed) {
-            (KeyCode::Escape, true) => event_loop.exit(),
-            (KeyCode::KeyR, true) => {
-                // Reset camera when R is pressed
-                #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(&"RESETTING CAMERA".into());
-                self.reset_camera();
-            },
-            //GUI: also move this to gui, and have it under the button "apply upward force"
-            (KeyCode::Space, true) => {
-                // Apply force to all bodies
-                for handle in &self.physics_bodies {
-                    self.physics_world.apply_force(*handle, cgmath::Vector3::new(0.0, 10.0, 0.0));
-                }
-            },
-            _ => {}
+    pub fn checkerboard(&self) -> Option<Checkerboard> {
+        self.checkerboard
+    }
+
+    /// Texture the ground tiles with `texture`, or go back to plain tile colors with `None`
+    /// It's kept for later `set_checkerboard` calls
+    pub fn set_ground_texture(&mut self, texture: Option<Texture>) {
+        self.ground_texture = texture;
+        let material = self.ground_material();
+        if let Some(ground_model) = &mut self.ground_model {
+            ground_model.materials = vec![material];
         }
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_system.input(event)
+    // The ground tiles' own material, separate from the cubes'
+    fn ground_material(&self) -> Material {
+        // Without a texture the tiles are untextured, the instance colors are the whole look
+        let texture = self.ground_texture.clone().unwrap_or_else(|| {
+            Texture::create_1x1_texture(&self.device, &self.queue, [255, 255, 255, 255], "ground_texture")
+        });
+        Material::from_texture(&self.device, &self.texture_bind_group_layout, "ground", texture)
+    }
+
+    /// The device everything is created on, e.g. to copy `offscreen_texture` somewhere readable
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Limits of the device we're rendering with, WebGL's are much lower than native ones
+    pub fn device_limits(&self) -> wgpu::Limits {
+        self.device.limits()
+    }
+
+    /// Most instances the instance buffer can hold on this device
+    pub fn max_instances(&self) -> usize {
+        (self.device.limits().max_buffer_size / std::mem::size_of::<InstanceRaw>() as u64) as usize
+    }
+
+    /// Instances uploaded by the last `sync_instances`, i.e. the ones that survived frustum culling
+    pub fn instance_count(&self) -> usize {
+        self.visible_instance_count
+    }
+
+    /// How many instances the GPU buffer can hold before it has to be reallocated, at least `instance_count`
+    pub fn instance_capacity(&self) -> usize {
+        self.instance_capacity
     }
 
+    /// Depth format picked for this adapter, see `Texture::select_depth_format`
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.depth_format
+    }
 
+    /// Snap the camera to look at a body from `distance` away, see `CameraSystem::look_at_body`
+    pub fn look_at_body(&mut self, world: &PhysicsWorld, handle: RigidBodyHandle, distance: f32) -> bool {
+        self.camera_system.look_at_body(world, handle, distance, &self.queue)
+    }
+
+    /// Place the camera at `eye` looking at `target`
+    pub fn look_at(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        self.camera_system.look_at(eye, target, &self.queue);
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera_system.input(event)
+    }
 
     pub fn resize(&mut self, width: u32, height: u32) {
-        let max_dim = 800;
-        let width = width.min(max_dim);
-        let height = height.min(max_dim);
+        let (width, height) = match self.max_resolution {
+            // Scale both sides by the same factor so the aspect ratio survives the cap
+            Some(max_dim) if width.max(height) > max_dim => {
+                let scale = max_dim as f32 / width.max(height) as f32;
+                (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1))
+            }
+            _ => (width, height),
+        };
 
-        if width > 0 && height > 0 {
-            self.config.width = width;
-            self.config.height = height;
-            self.camera_system.update_aspect(width, height);
-            self.surface.configure(&self.device, &self.config);
-            self.is_surface_configured = true;
-            
-            // Recreate depth texture with new dimensions
-            self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-            
+        if width == 0 || height == 0 {
+            return;
+        }
+        // Drag-resizing sends lots of events with the same size, don't reallocate for those
+        if self.is_surface_configured && width == self.config.width && height == self.config.height {
+            return;
+        }
+
+        self.config.width = width;
+        self.config.height = height;
+        self.camera_system.update_aspect(width, height);
+        self.reconfigure();
+        self.picker.resize(&self.device, &self.config, self.depth_format);
+    }
 
+    /// Configure the surface and recreate the depth texture at the current size
+    /// Use after the surface is lost or outdated, when `resize` would skip it because the size hasn't changed
+    pub fn reconfigure(&mut self) {
+        if self.config.width == 0 || self.config.height == 0 {
+            return;
         }
+        self.config.present_mode = self.present_mode.resolve(&self.supported_present_modes);
+        match &mut self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+            RenderTarget::Offscreen(texture) => {
+                texture.destroy();
+                *texture = create_offscreen_texture(&self.device, &self.config);
+            }
+        }
+        self.is_surface_configured = true;
+        self.queue.write_buffer(
+            &self.screen_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[screen_space_matrix(self.config.width, self.config.height)]),
+        );
+
+        // Recreate depth texture with new dimensions
+        self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, self.depth_format, "depth_texture");
     }
     
-    pub fn update(&mut self) {
-        // Step physics simulation (assuming 60 FPS = 1/60 seconds)
-        let delta_time = 1.0 / 60.0;
-        self.physics_world.step(delta_time);
-        
+    /// Pull the latest body transforms from `world` and update the camera
+    /// `dt` is the time since the last update in seconds
+    pub fn update(&mut self, world: &PhysicsWorld, dt: f32) {
+        // Update camera system first so transparent instances are sorted against this frame's eye
+        self.update_camera(world, dt);
+
         // Update instances based on physics bodies
-        self.update_instances_from_physics();
-        
-        // Update camera system
-        self.camera_system.update(&self.queue);
-    }   
-    
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+        self.sync_instances(world);
+    }
+
+    /// Move the camera for this frame without touching the instances, returns true if the view changed
+    pub fn update_camera(&mut self, world: &PhysicsWorld, dt: f32) -> bool {
+        self.camera_system.update(&self.queue, world, dt)
+    }
+
+    /// Switch to an orthographic view looking straight down at every body in `world`, or back again
+    /// Returns true if the top-down view is now on
+    pub fn toggle_top_down(&mut self, world: &PhysicsWorld) -> bool {
+        self.camera_system.toggle_top_down(world, &self.queue)
+    }
+
+    /// Shake the view by up to `intensity` world units for `duration` seconds, see `CameraSystem::add_shake`
+    pub fn add_camera_shake(&mut self, intensity: f32, duration: f32) {
+        self.camera_system.add_shake(intensity, duration);
+    }
+
+    /// The camera as JSON, see `CameraSystem::serialize`
+    pub fn serialize_camera(&self) -> String {
+        self.camera_system.serialize()
+    }
+
+    /// Restore a camera saved by `serialize_camera`, leaves it alone if `json` is invalid
+    pub fn deserialize_camera(&mut self, json: &str) -> anyhow::Result<()> {
+        self.camera_system.deserialize(json, &self.queue)
+    }
+
+    /// Set the camera's near and far clip planes, returns false if they're invalid (see `Camera::set_near_far`)
+    /// Raise `far` for scenes larger than the default 100 units so distant bodies aren't clipped
+    pub fn set_near_far(&mut self, near: f32, far: f32) -> bool {
+        self.camera_system.set_near_far(near, far, &self.queue)
+    }
+
+    /// The camera's near and far clip planes
+    pub fn near_far(&self) -> (f32, f32) {
+        (self.camera_system.camera.near(), self.camera_system.camera.far())
+    }
+
+    /// Free the GPU buffers and textures now instead of whenever wgpu gets around to it
+    /// With `wait` this blocks until the GPU has finished with them, e.g. before recreating a renderer
+    /// for the same window or canvas. The surface is released when the renderer is dropped at the end
+    pub fn destroy(mut self, wait: bool) {
+        self.instance_buffer.destroy();
+        if let Some(buffer) = self.ground_instance_buffer.take() {
+            buffer.destroy();
+        }
+        self.screen_uniform_buffer.destroy();
+        self.depth_texture.texture.destroy();
+        if let RenderTarget::Offscreen(texture) = &self.target {
+            texture.destroy();
+        }
+        self.picker.destroy();
+        self.diffuse_texture.texture.destroy();
+        self.overlay.clear();
+        self.resource_manager.clear();
+        if wait && let Err(e) = self.device.poll(wgpu::PollType::Wait) {
+            log::warn!("Waiting for the GPU while destroying the renderer failed: {}", e);
+        }
+        self.destroyed = true;
+    }
+
+    /// Triangles drawn over the finished frame with depth testing off, until replaced or cleared
+    /// Every three `indices` make a triangle. With `screen_space` positions are window pixels
+    /// (origin top left, y down), otherwise world units seen through the camera
+    pub fn set_overlay(&mut self, vertices: &[DebugVertex], indices: &[u32], screen_space: bool) {
+        self.overlay.set(&self.device, vertices, indices);
+        self.overlay_screen_space = screen_space;
+    }
+
+    pub fn clear_overlay(&mut self) {
+        self.overlay.clear();
+    }
+
+    /// How long the GPU spent on the main render pass of a recent frame, in milliseconds
+    /// None when timestamp queries aren't supported or no result has been read back yet
+    pub fn last_gpu_frame_ms(&self) -> Option<f32> {
+        self.gpu_timer.as_ref().and_then(|timer| timer.last_ms())
+    }
+
+    /// Throw a burst of sparks out from `point`, does nothing without compute shader support
+    /// `intensity` from 0 to 1 sets how many
+    pub fn spawn_sparks(&mut self, point: cgmath::Vector3<f32>, intensity: f32) {
+        if let Some(particles) = &mut self.particles {
+            particles.burst(&self.queue, point, intensity);
+        }
+    }
+
+    /// Advance the sparks by `dt` seconds, the GPU work runs in the next `render`
+    pub fn update_particles(&mut self, dt: f32) {
+        if let Some(particles) = &mut self.particles {
+            particles.update(&self.queue, dt);
+        }
+    }
+
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
 
         // We can't render unless the surface is configured
         if !self.is_surface_configured {
             return Ok(());
         }
         
-        //asks surface to give a new surfacetexture that we render to
-        let output = self.surface.get_current_texture()?;
-        
         //honestly not sure wtf this is but you should apparently get a textureview to control how the renderer interacts w texture
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (output, view) = match &self.target {
+            //asks surface to give a new surfacetexture that we render to
+            RenderTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            // Offscreen there's nothing to present, the same texture is drawn into every frame
+            RenderTarget::Offscreen(texture) => (None, texture.create_view(&wgpu::TextureViewDescriptor::default())),
+        };
 
         //create a command buffer to send data to the GPU
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor{
             label: Some("Render Encoder"),
         });
 
+        // Move the sparks before they're drawn
+        if let Some(particles) = &mut self.particles {
+            particles.dispatch(&mut encoder);
+        }
+        // Any of the camera methods may have moved it since the last frame
+        self.fog.set_eye(self.camera_system.camera.get_eye(), &self.queue);
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -451,12 +1679,7 @@ impl State {
                         view: &view,
                         resolve_target: None,
                         ops: wgpu::Operations { 
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
+                            load: wgpu::LoadOp::Clear(self.clear_color),
                             store: wgpu::StoreOp::Store,
                         },
                 })],
@@ -469,66 +1692,1057 @@ impl State {
                     stencil_ops: None,
                 }),
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: self.gpu_timer.as_ref().map(|timer| timer.timestamp_writes()),
             });
 
             //for working with the shaders and the pipeline
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.draw_model_instanced(&self.obj_model, 0..self.instances.len() as u32, self.camera_system.bind_group());
+            render_pass.set_pipeline(if self.backface_culling { &self.render_pipeline } else { &self.no_cull_pipeline });
+            render_pass.set_bind_group(2, self.light_system.bind_group(), &[]);
+            render_pass.set_bind_group(3, self.fog.bind_group(), &[]);
+            if let (Some(ground_model), Some(ground_instances)) = (&self.ground_model, &self.ground_instance_buffer) {
+                draw_instances(
+                    &mut render_pass,
+                    ground_model,
+                    ground_instances,
+                    0..self.ground_instance_count,
+                    self.camera_system.bind_group(),
+                );
+            }
+            draw_instances(
+                &mut render_pass,
+                &self.obj_model,
+                &self.instance_buffer,
+                0..self.opaque_instance_count as u32,
+                self.camera_system.bind_group(),
+            );
+
+            // Transparent instances go last, already sorted back to front
+            if self.opaque_instance_count < self.visible_instance_count {
+                render_pass.set_pipeline(&self.transparent_pipeline);
+                draw_instances(
+                    &mut render_pass,
+                    &self.obj_model,
+                    &self.instance_buffer,
+                    self.opaque_instance_count as u32..self.visible_instance_count as u32,
+                    self.camera_system.bind_group(),
+                );
+            }
+
+            if let Some(particles) = &self.particles {
+                particles.draw(&mut render_pass, self.camera_system.bind_group());
+            }
+
+            if self.show_colliders {
+                self.debug_lines.draw(&mut render_pass, self.camera_system.bind_group());
+            }
+            if self.show_normals {
+                self.normal_lines.draw(&mut render_pass, self.camera_system.bind_group());
+            }
+        }
+
+        // Labels and stats get their own pass without a depth attachment, like the overlay, so bodies can't cover them
+        if self.show_labels || self.show_stats {
+            let mut label_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Label Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            if self.show_labels {
+                self.labels.draw(&mut label_pass, &self.screen_bind_group);
+            }
+            if self.show_stats {
+                self.stats.draw(&mut label_pass, &self.screen_bind_group);
+            }
+        }
+
+        // The overlay gets its own pass without a depth attachment, so nothing in the scene can hide it
+        if !self.overlay.is_empty() {
+            let mut overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            let bind_group = if self.overlay_screen_space { &self.screen_bind_group } else { self.camera_system.bind_group() };
+            self.overlay.draw(&mut overlay_pass, bind_group);
+        }
+
+        if self.picker.wants_pass() {
+            {
+                let mut picking_pass = self.picker.begin_pass(&mut encoder);
+                picking_pass.set_pipeline(&self.picking_pipeline);
+                if self.visible_instance_count > 0 {
+                    picking_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                    picking_pass.draw_model_geometry_instanced(
+                        &self.obj_model,
+                        0..self.visible_instance_count as u32,
+                        self.camera_system.bind_group(),
+                    );
+                }
+            }
+            self.picker.copy(&mut encoder, self.visible_handles.clone());
+        }
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
         }
 
         //encoder.finish() ends the CommandEncoder and returns a CommandBuffer, ready to be passed on to the GPU
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.after_submit();
+        }
+        self.picker.after_submit();
+        if let Some(output) = output {
+            output.present();
+        }
 
         Ok(())
     }
 
 
-    // Add this method to State
-    fn reset_camera(&mut self) {
+    /// Look up the body drawn at surface pixel (`x`, `y`), replacing a lookup that hasn't been drawn yet
+    /// The ids are drawn with the next `render` and read back without waiting, collect the answer with `take_pick`
+    pub fn request_pick(&mut self, x: u32, y: u32) {
+        self.picker.request(x, y);
+    }
+
+    /// The answer to the last `request_pick` once the GPU has it, usually a frame or two after the request
+    pub fn take_pick(&mut self) -> Option<PickResult> {
+        self.picker.take_result(&self.device)
+    }
+
+    pub fn reset_camera(&mut self, world: &PhysicsWorld) {
         // Update instances first to get current positions
-        self.update_instances_from_physics();
+        self.sync_instances(world);
         
         // Position camera to look at the center of all instances
         self.position_camera_at_instances_center();
     }
 
-    fn update_instances_from_physics(&mut self) {
-        let bodies = self.physics_world.get_bodies();
+    /// Rebuild the instance list and GPU buffer from the bodies in `world`
+    pub fn sync_instances(&mut self, world: &PhysicsWorld) {
+        let bodies = world.get_bodies();
         
         // Clear existing instances and create new ones from physics bodies
         self.instances.clear();
         
-        for (_handle, body_data) in bodies {
+        for (handle, body_data) in bodies {
             // Only add dynamic bodies to rendering (skip ground plane)
             if body_data.is_dynamic {
                 self.instances.push(Instance {
                     position: body_data.position,
                     rotation: body_data.rotation,
+                    // The cube model is 1 unit across, so scaling by the shape's size matches the collider
+                    scale: body_data.shape.size(),
+                    alpha: body_data.alpha,
+                    color: body_data.color,
+                    handle: Some(*handle),
                 });
             }
         }
+
+        // Opaque instances first, then transparent ones from farthest to nearest so blending works
+        let eye = self.camera_system.camera.get_eye().to_vec();
+        self.instances.sort_by(|a, b| {
+            a.is_transparent().cmp(&b.is_transparent()).then_with(|| {
+                if a.is_transparent() {
+                    let distance_a = (a.position - eye).magnitude2();
+                    let distance_b = (b.position - eye).magnitude2();
+                    distance_b.total_cmp(&distance_a)
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+        });
+
+        // Skip anything the camera can't see. All instances are kept for camera framing, only the upload is culled
+        let frustum = Frustum::from_matrix(&self.camera_system.camera.build_view_projection_matrix());
+        let is_visible = |instance: &&Instance| {
+            !self.frustum_culling
+                || frustum.contains_sphere(cgmath::Point3::from_vec(instance.position), instance.bounding_radius())
+        };
+        self.opaque_instance_count = self.instances.iter()
+            .filter(is_visible)
+            .take_while(|i| !i.is_transparent())
+            .count();
         
-        // Update GPU buffer with new instance data
-        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        // Update GPU buffer with new instance data, reusing last frame's allocation
+        self.instance_data.clear();
+        self.visible_handles.clear();
+        // A buffer over the device's limit fails to allocate and nothing gets drawn, so drop whatever doesn't fit
+        let max_instances = self.max_instances();
+        let visible = self.instances.iter().filter(is_visible).count();
+        if visible > max_instances {
+            // This runs every frame, only say so once each time the limit is crossed
+            if !self.instance_limit_warned {
+                log::warn!("{} instances are visible but this device can only draw {}, skipping the rest", visible, max_instances);
+                self.instance_limit_warned = true;
+            }
+            self.opaque_instance_count = self.opaque_instance_count.min(max_instances);
+        } else {
+            self.instance_limit_warned = false;
+        }
+        for instance in self.instances.iter().filter(is_visible).take(max_instances) {
+            self.visible_handles.push(instance.handle);
+            self.instance_data.push(instance.to_raw(self.instance_data.len() as u32 + 1));
+        }
+        self.visible_instance_count = self.instance_data.len();
         
-        // Recreate buffer if size changed
-        if instance_data.len() * std::mem::size_of::<InstanceRaw>() != self.instance_buffer.size() as usize {
-            self.instance_buffer = self.device.create_buffer_init(
-                &wgpu::util::BufferInitDescriptor {
-                    label: Some("Instance Buffer"),
-                    contents: bytemuck::cast_slice(&instance_data),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                }
-            );
+        // The logical length (visible_instance_count) is tracked apart from the capacity, so only
+        // recreate the buffer when it's too small. When it shrinks the stale tail is never drawn
+        if let Some(capacity) = grown_instance_capacity(self.instance_capacity, self.visible_instance_count, max_instances) {
+            self.instance_buffer = create_instance_buffer(&self.device, capacity);
+            self.instance_capacity = capacity;
+        }
+        self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.instance_data));
+
+        if self.show_colliders {
+            self.sync_collider_lines(world);
+        }
+        if self.show_normals {
+            self.sync_normal_lines(world);
+        }
+        if self.show_labels {
+            self.sync_labels(world);
+        }
+    }
+
+    /// Rebuild the normal lines from the cube model's vertices, placed like each body's instance
+    fn sync_normal_lines(&mut self, world: &PhysicsWorld) {
+        self.normal_lines.clear();
+        for body in world.get_bodies().values().filter(|body| body.is_dynamic) {
+            let scale = body.shape.size();
+            // Long enough to see, short enough not to reach across small bodies
+            let length = NORMAL_LINE_LENGTH * scale.x.min(scale.y).min(scale.z);
+            for mesh in &self.obj_model.meshes {
+                self.normal_lines.normals(&mesh.vertices, body.position, body.rotation, scale, length);
+            }
+        }
+        self.normal_lines.upload(&self.device, &self.queue);
+    }
+
+    /// Rebuild the labels: each body's handle index, just above the top of its shape
+    fn sync_labels(&mut self, world: &PhysicsWorld) {
+        let camera = &self.camera_system.camera;
+        let viewport = (self.config.width, self.config.height);
+        let height = LABEL_HEIGHT * self.scale_factor();
+        for (handle, body) in world.get_bodies() {
+            let above = body.position + cgmath::Vector3::unit_y() * (body.shape.size().y / 2.0 + 0.3);
+            if let Some(center) = camera.world_to_screen(cgmath::Point3::from_vec(above), viewport) {
+                let (index, _) = handle.into_raw_parts();
+                self.labels.centered(center, height, &index.to_string(), [1.0, 1.0, 1.0, 1.0]);
+            }
+        }
+        self.labels.upload(&self.device, &self.queue);
+    }
+
+    /// Rebuild the collider outlines: dynamic bodies in green, static geometry like the ground in yellow
+    fn sync_collider_lines(&mut self, world: &PhysicsWorld) {
+        self.debug_lines.clear();
+        for body in world.get_bodies().values() {
+            let color = if body.is_dynamic { [0.2, 1.0, 0.2] } else { [1.0, 0.9, 0.2] };
+            self.debug_lines.shape(body.position, body.rotation, body.shape, color);
+        }
+        for collider in world.static_colliders() {
+            self.debug_lines.shape(collider.position, collider.rotation, collider.shape, [1.0, 0.9, 0.2]);
+        }
+        self.debug_lines.upload(&self.device, &self.queue);
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        if !self.destroyed {
+            log::debug!("Renderer dropped without destroy, GPU resources are freed lazily");
+        }
+    }
+}
+
+impl State {
+    /// Create the demo state with default settings, see `StateBuilder` to customize it
+    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+        StateBuilder::default().build(window).await
+    }
+
+    async fn from_builder(window: Arc<Window>, builder: StateBuilder) -> anyhow::Result<Self> {
+        let mut renderer = Renderer::new(window.clone(), &builder).await?;
+        // The demo shows its frame rate until F5 hides it
+        renderer.set_show_stats(true);
+
+        // Initialize physics world
+        let mut physics_world = PhysicsWorld::new();
+        
+        // Add ground plane
+        physics_world.add_ground();
+        if let Some(path) = &builder.ground_texture_path {
+            renderer.set_ground_texture(Some(load_texture_at(&renderer.device, &renderer.queue, path).await?));
+        }
+        if let Some(mut checkerboard) = builder.checkerboard {
+            // Lay the tiles on the ground's top surface
+            if let Some(ground) = physics_world.ground() {
+                checkerboard.y = ground.y;
+            }
+            renderer.set_checkerboard(Some(checkerboard));
+        }
+        
+        // GUI: Add some physics cubes -> replace with gui functionality later to user can add these and create seperate file and functions for handling addition of objects via the gui
+        //GUI: modify this and have it as a button to add cubes, and under another panel that has a list of all the pbject, drop down for each cube and be able to modify its x,y,z and its rotations
+        let physics_bodies = if builder.default_grid {
+            physics_world.spawn_grid(builder.grid_rows, builder.grid_cols, builder.grid_spacing, builder.grid_drop_height, 1.0)
         } else {
-            self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+            Vec::new()
+        };
+
+        // Update instances from physics bodies to get initial positions
+        renderer.sync_instances(&physics_world);
+        
+        match (builder.camera_eye, builder.camera_target) {
+            (Some(eye), Some(target)) => renderer.look_at(eye, target),
+            // Position camera to look at the center of all instances
+            _ => renderer.position_camera_at_instances_center(),
         }
+        if builder.persist_camera
+            && builder.camera_eye.is_none()
+            && let Some(json) = resources::load_setting(CAMERA_SETTING)
+        {
+            // Anything unreadable keeps the default camera
+            if let Err(e) = renderer.deserialize_camera(&json) {
+                log::warn!("Ignoring the saved camera: {:#}", e);
+            }
+        }
+
+        Ok(Self {
+            renderer,
+            window,
+            sim: SimLoop::new(physics_world),
+            physics_bodies,
+            last_update: Instant::now(),
+            key_bindings: KeyBindings::default(),
+            frame_stats: FrameStats::default(),
+            cursor_position: None,
+            instances_dirty: false,
+            spawn_height: DEFAULT_SPAWN_HEIGHT,
+            spawn_size: 1.0,
+            grab: None,
+            shake_intensity: DEFAULT_SHAKE_INTENSITY,
+            shake_duration: DEFAULT_SHAKE_DURATION,
+            persist_camera: builder.persist_camera,
+            camera_save_pending: false,
+            last_camera_save: Instant::now(),
+        })
     }
 
+    pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
+        match (self.key_bindings.action_for(code), is_pressed) {
+            (Some(Action::Exit), true) => event_loop.exit(),
+            (Some(Action::Reset), true) => {
+                // Reset camera when R is pressed
+                self.renderer.reset_camera(self.sim.world());
+            },
+            //GUI: also move this to gui, and have it under the button "apply upward force"
+            (Some(Action::Jump), true) => {
+                // Apply force to all bodies
+                for handle in &self.physics_bodies {
+                    self.sim.world_mut().apply_force(*handle, cgmath::Vector3::new(0.0, 10.0, 0.0));
+                }
+            },
+            (Some(Action::Shoot), true) => {
+                self.shoot_cube();
+            },
+            (Some(Action::Explode), true) => {
+                self.explode_at_view_center();
+            },
+            (Some(Action::Freeze), true) => {
+                // Bodies keep moving, just lose their velocity quickly
+                for handle in &self.physics_bodies {
+                    self.sim.world_mut().set_linear_damping(*handle, FREEZE_DAMPING);
+                    self.sim.world_mut().set_angular_damping(*handle, FREEZE_DAMPING);
+                }
+            },
+            (Some(Action::Unfreeze), true) => {
+                for handle in &self.physics_bodies {
+                    self.sim.world_mut().set_linear_damping(*handle, 0.0);
+                    self.sim.world_mut().set_angular_damping(*handle, 0.0);
+                }
+            },
+            (Some(Action::ToggleColliders), true) => {
+                let show = !self.renderer.show_colliders();
+                self.renderer.set_show_colliders(show);
+                // Rebuild right away, a settled scene wouldn't otherwise sync until something moves
+                self.renderer.sync_instances(self.sim.world());
+            },
+            (Some(Action::ToggleNormals), true) => {
+                let show = !self.renderer.show_normals();
+                self.renderer.set_show_normals(show);
+                self.renderer.sync_instances(self.sim.world());
+            },
+            (Some(Action::ToggleLabels), true) => {
+                let show = !self.renderer.show_labels();
+                self.renderer.set_show_labels(show);
+                self.renderer.sync_instances(self.sim.world());
+            },
+            (Some(Action::ToggleStats), true) => {
+                let show = !self.renderer.show_stats();
+                self.renderer.set_show_stats(show);
+            },
+            (Some(Action::ToggleTopDown), true) => {
+                let top_down = self.renderer.toggle_top_down(self.sim.world());
+                log::info!("Top-down view {}", if top_down { "on" } else { "off" });
+                // The view jumped, cull against it right away
+                self.renderer.sync_instances(self.sim.world());
+            },
+            (Some(Action::ToggleCulling), true) => {
+                let enabled = !self.renderer.backface_culling();
+                self.renderer.set_backface_culling(enabled);
+                log::info!("Backface culling {}", if enabled { "on" } else { "off" });
+            },
+            (Some(Action::SavePreset), true) => {
+                let (slot, replaced) = self.renderer.camera_system.save_next_preset();
+                if replaced {
+                    log::info!("Camera preset slots are full, replaced the oldest in slot {}", slot);
+                } else {
+                    log::info!("Saved camera preset {}", slot);
+                }
+            },
+            (Some(Action::LoadScene(index)), true) => {
+                if let Some(scene) = Scene::ALL.get(index) {
+                    self.load_scene(*scene);
+                }
+            },
+            (Some(Action::CyclePreset), true) => {
+                if let Some(slot) = self.renderer.camera_system.cycle_preset(&self.renderer.queue) {
+                    log::info!("Loaded camera preset {}", slot);
+                }
+            },
+            _ => {}
+        }
+    }
 
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.renderer.input(event)
+    }
+
+    /// Remember where the mouse is, for `spawn_cube_at_cursor`
+    pub fn cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.cursor_position = Some(position);
+    }
 
+    /// The body under the mouse, found with a ray cast against the colliders so the answer is immediate
+    /// `Renderer::request_pick` finds what was actually drawn instead, a frame or two later
+    pub fn pick_at_cursor(&self) -> Option<RigidBodyHandle> {
+        let (ndc_x, ndc_y) = self.cursor_ndc()?;
+        let ray = self.renderer.camera_system.camera.screen_to_ray(ndc_x, ndc_y)?;
+        let (handle, _) = self.sim.world().cast_ray(ray.origin, ray.direction, PICK_DISTANCE)?;
+        Some(handle)
+    }
+
+    /// The mouse position in normalized device coordinates
+    fn cursor_ndc(&self) -> Option<(f32, f32)> {
+        let position = self.cursor_position?;
+        let size = self.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return None;
+        }
+
+        // Window pixels have y pointing down, NDC has it pointing up
+        let ndc_x = (position.x as f32 / size.width as f32) * 2.0 - 1.0;
+        let ndc_y = 1.0 - (position.y as f32 / size.height as f32) * 2.0;
+        Some((ndc_x, ndc_y))
+    }
+
+    /// Drop a cube above the point on the ground under the mouse
+    pub fn spawn_cube_at_cursor(&mut self) -> Option<RigidBodyHandle> {
+        let (ndc_x, ndc_y) = self.cursor_ndc()?;
+        self.spawn_cube_at_screen(ndc_x, ndc_y)
+    }
+
+    /// Start dragging the body under the mouse, it follows the cursor until `release_grab`
+    pub fn grab_at_cursor(&mut self) -> Option<RigidBodyHandle> {
+        let handle = self.pick_at_cursor()?;
+        let body = self.sim.world().get_body(handle)?;
+        let camera = &self.renderer.camera_system.camera;
+        let plane_normal = (camera.get_target() - camera.get_eye()).normalize();
+        self.grab = Some(Grab {
+            handle,
+            plane_point: cgmath::Point3::from_vec(body.position),
+            plane_normal,
+        });
+        Some(handle)
+    }
+
+    /// Let go of the dragged body, it keeps whatever velocity it had so it can be flung
+    pub fn release_grab(&mut self) {
+        self.grab = None;
+    }
+
+    /// The body being dragged with the mouse
+    pub fn grabbed_body(&self) -> Option<RigidBodyHandle> {
+        self.grab.map(|grab| grab.handle)
+    }
+
+    /// Pull the grabbed body towards the point under the cursor with a damped spring
+    fn apply_grab(&mut self, dt: f32) {
+        let Some(grab) = self.grab else {
+            return;
+        };
+        let Some(body) = self.sim.world().get_body(grab.handle) else {
+            // Despawned while held
+            self.grab = None;
+            return;
+        };
+        let target = self
+            .cursor_ndc()
+            .and_then(|(ndc_x, ndc_y)| self.renderer.camera_system.camera.screen_to_ray(ndc_x, ndc_y))
+            .and_then(|ray| ray.intersect_plane(grab.plane_point, grab.plane_normal));
+        let Some(target) = target else {
+            return;
+        };
+
+        // Cancel gravity too, or the body would hang below the cursor
+        let world = self.sim.world();
+        let acceleration = (target.to_vec() - body.position) * GRAB_STIFFNESS
+            - body.linear_velocity * GRAB_DAMPING
+            - world.gravity();
+        let mass = world.get_mass(grab.handle).unwrap_or(1.0);
+        let position = body.position;
+        // A long frame would overshoot and make the spring unstable
+        let dt = dt.min(GRAB_MAX_DT);
+        self.sim.world_mut().apply_impulse_at_point(grab.handle, acceleration * mass * dt, position);
+    }
+
+    /// Drop a cube above the point on the ground at normalized device coordinates (`ndc_x`, `ndc_y`)
+    /// Returns `None` if that point on screen doesn't look at the ground (e.g. it's sky)
+    pub fn spawn_cube_at_screen(&mut self, ndc_x: f32, ndc_y: f32) -> Option<RigidBodyHandle> {
+        let ray = self.renderer.camera_system.camera.screen_to_ray(ndc_x, ndc_y)?;
+        let hit = ray.intersect_plane_y(0.0)?;
+
+        let position = cgmath::Vector3::new(hit.x, self.spawn_height, hit.z);
+        self.spawn_cube(position, self.spawn_size)
+    }
+
+    /// Blast bodies away from where the middle of the view meets the ground, or from the camera
+    /// target when looking above the horizon. See `PhysicsWorld::explode`
+    pub fn explode_at_view_center(&mut self) {
+        let camera = &self.renderer.camera_system.camera;
+        let ground_y = self.sim.world().ground().map(|ground| ground.y).unwrap_or(0.0);
+        let center = camera
+            .screen_to_ray(0.0, 0.0)
+            .and_then(|ray| ray.intersect_plane_y(ground_y))
+            .unwrap_or_else(|| camera.get_target())
+            .to_vec();
+        self.sim.world_mut().explode(center, EXPLOSION_RADIUS, EXPLOSION_STRENGTH);
+        self.renderer.spawn_sparks(center, 1.0);
+        self.renderer.add_camera_shake(self.shake_intensity, self.shake_duration);
+    }
+
+    /// Launch a cube from just in front of the camera along the view direction
+    pub fn shoot_cube(&mut self) -> Option<RigidBodyHandle> {
+        let camera = &self.renderer.camera_system;
+        let forward = camera.camera_controller.forward();
+        let position = camera.camera.get_eye().to_vec() + forward * (SHOOT_DISTANCE + self.spawn_size / 2.0);
+
+        let handle = self.spawn_cube(position, self.spawn_size)?;
+        let world = self.sim.world_mut();
+        let mass = world.get_mass(handle).unwrap_or(1.0);
+        world.apply_impulse_at_point(handle, forward * SHOOT_SPEED * mass, position);
+        Some(handle)
+    }
+
+    /// Height above the ground that cubes dropped with the mouse start at
+    pub fn set_spawn_height(&mut self, height: f32) {
+        self.spawn_height = height;
+    }
+
+    pub fn spawn_height(&self) -> f32 {
+        self.spawn_height
+    }
+
+    /// Side length of cubes spawned with the mouse or the shoot key
+    pub fn set_spawn_size(&mut self, size: f32) {
+        self.spawn_size = size.max(0.01);
+    }
+
+    pub fn spawn_size(&self) -> f32 {
+        self.spawn_size
+    }
+
+    /// Add a dynamic cube to the world and include it in scene-wide actions like Jump
+    /// Returns `None` (with a warning) once there are as many bodies as the device can draw
+    pub fn spawn_cube(&mut self, position: cgmath::Vector3<f32>, size: f32) -> Option<RigidBodyHandle> {
+        if !self.has_room_for(self.sim.world().get_bodies().len(), 1, "a cube") {
+            return None;
+        }
+        let handle = self.sim.world_mut().add_cube(position, size);
+        let evicted = self.sim.world_mut().take_despawned();
+        self.physics_bodies.retain(|handle| !evicted.contains(handle));
+        self.physics_bodies.push(handle);
+        Some(handle)
+    }
+
+    /// Texture the ground tiles with the image at `path` (fetched relative to the page on the web),
+    /// or go back to plain tile colors with `None`. On error the ground is left as it was
+    pub async fn set_ground_texture(&mut self, path: Option<&str>) -> anyhow::Result<()> {
+        let texture = match path {
+            Some(path) => Some(load_texture_at(&self.renderer.device, &self.renderer.queue, path).await?),
+            None => None,
+        };
+        self.renderer.set_ground_texture(texture);
+        Ok(())
+    }
+
+    // Whether `count` more bodies next to `existing` ones can all be drawn, warns about `what` if not
+    // With a body limit spawning past it evicts, so the total never goes over the limit
+    fn has_room_for(&self, existing: usize, count: usize, what: &str) -> bool {
+        let max_instances = self.renderer.max_instances();
+        let total = existing + count;
+        let total = self.sim.world().max_bodies().map_or(total, |max| total.min(max));
+        if total > max_instances {
+            log::warn!("Not spawning {}, this device can only draw {} instances", what, max_instances);
+            return false;
+        }
+        true
+    }
+
+    /// Replace every body with `scene` and frame the camera on it
+    /// Does nothing if the device can't draw that many bodies
+    pub fn load_scene(&mut self, scene: Scene) {
+        if !self.has_room_for(0, scene.body_count(), &format!("the {} scene", scene.name())) {
+            return;
+        }
+        self.sim.world_mut().clear_bodies();
+        self.physics_bodies = scene.spawn(self.sim.world_mut());
+        self.renderer.sync_instances(self.sim.world());
+        self.renderer.position_camera_at_instances_center();
+        log::info!("Loaded the {} scene", scene.name());
+    }
+
+    /// Snap the camera to look at a body from `distance` away, returns false if it doesn't exist
+    pub fn look_at_body(&mut self, handle: RigidBodyHandle, distance: f32) -> bool {
+        let moved = self.renderer.look_at_body(self.sim.world(), handle, distance);
+        // The view changed outside of update, so culling needs redoing even in a settled scene
+        self.instances_dirty |= moved;
+        moved
+    }
+
+    /// Limits of the GPU device, e.g. `max_buffer_size` which caps how many bodies can be drawn
+    pub fn device_limits(&self) -> wgpu::Limits {
+        self.renderer.device_limits()
+    }
+
+    pub fn physics_world(&self) -> &PhysicsWorld {
+        self.sim.world()
+    }
+
+    /// Mutable access to the simulation, instances are rebuilt on the next update to pick up any changes
+    pub fn physics_world_mut(&mut self) -> &mut PhysicsWorld {
+        self.instances_dirty = true;
+        self.sim.world_mut()
+    }
+
+    /// Fade distant bodies towards `color`, see `Renderer::set_fog`. A density of 0 turns it off (the default)
+    pub fn set_fog(&mut self, color: [f32; 3], density: f32) {
+        self.renderer.set_fog(color, density);
+    }
+
+    pub fn fog(&self) -> ([f32; 3], f32) {
+        self.renderer.fog()
+    }
+
+    /// How far (world units) and how long (seconds) the camera shakes after the hardest impacts
+    /// Softer impacts shake it less, pass an intensity of 0 to turn it off. Defaults to 0.15 and 0.3
+    pub fn set_impact_shake(&mut self, intensity: f32, duration: f32) {
+        self.shake_intensity = intensity.max(0.0);
+        self.shake_duration = duration.max(0.0);
+    }
 
-} 
\ No newline at end of file
+    pub fn impact_shake(&self) -> (f32, f32) {
+        (self.shake_intensity, self.shake_duration)
+    }
+
+    /// Cap on physics steps per frame, after a slow frame the rest of the backlog is dropped (default 5)
+    pub fn set_max_physics_steps_per_frame(&mut self, max_steps: u32) {
+        self.sim.set_max_steps_per_run(max_steps);
+    }
+
+    pub fn max_physics_steps_per_frame(&self) -> u32 {
+        self.sim.max_steps_per_run()
+    }
+
+    /// Remap the keyboard controls, for both the camera and the scene actions
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.renderer.camera_system.camera_controller.set_key_bindings(key_bindings.clone());
+        self.key_bindings = key_bindings;
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.renderer.resize(width, height);
+        // Labels are placed in pixels, rebuild them even if nothing in the scene moved
+        self.instances_dirty = true;
+    }
+
+    /// Resize to the window's current physical size, e.g. after its scale factor changed
+    pub fn resize_to_window(&mut self) {
+        let size = self.window.inner_size();
+        self.resize(size.width, size.height);
+    }
+
+    /// Reconfigure the surface at its current size, e.g. after `RenderError::Reconfigure`
+    pub fn reconfigure(&mut self) {
+        self.renderer.reconfigure();
+    }
+
+    /// Advance everything by the time since the last call, for the winit loop in `App`
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let frame_time = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+        self.advance_frame(frame_time);
+    }
+
+    /// Advance by `dt` seconds for callers running their own loop: fixed physics steps, effects,
+    /// the camera and instances, without touching the surface. Returns every body's state afterwards
+    /// Pair it with `present` to draw. Steps depend only on `dt`, so feeding fixed values is deterministic
+    pub fn advance(&mut self, dt: f32) -> Vec<BodySummary> {
+        self.advance_frame(dt);
+        self.sim.world().body_summaries()
+    }
+
+    /// Draw the current state to the window, see `advance`
+    pub fn present(&mut self) -> Result<(), RenderError> {
+        self.renderer.render()
+    }
+
+    fn advance_frame(&mut self, frame_time: f32) {
+        // Before the settled check, a grab wakes the body it holds
+        self.apply_grab(frame_time);
+
+        // Once everything is asleep stepping and rebuilding instances changes nothing, so skip both
+        // until a force or spawn wakes a body or the camera moves
+        let settled = self.sim.world().all_asleep();
+
+        // Step physics in fixed increments so the simulation runs at the same speed at any frame rate
+        let physics_steps = self.sim.run(frame_time);
+        let despawned = self.sim.world_mut().take_despawned();
+        if !despawned.is_empty() {
+            self.physics_bodies.retain(|handle| !despawned.contains(handle));
+        }
+        let mut hardest_impact = 0.0f32;
+        for collision in self.sim.world_mut().take_collisions() {
+            // Harder hits throw more sparks
+            self.renderer.spawn_sparks(collision.point, collision.impulse / FULL_SPARKS_IMPULSE);
+            hardest_impact = hardest_impact.max(collision.impulse);
+        }
+        if hardest_impact >= SHAKE_IMPULSE {
+            let strength = (hardest_impact / FULL_SPARKS_IMPULSE).min(1.0);
+            self.renderer.add_camera_shake(self.shake_intensity * strength, self.shake_duration);
+        }
+        self.renderer.update_particles(frame_time);
+
+        let camera_moved = self.renderer.update_camera(self.sim.world(), frame_time);
+        if self.persist_camera {
+            self.camera_save_pending |= camera_moved;
+            if self.camera_save_pending && Instant::now().duration_since(self.last_camera_save).as_secs_f32() >= CAMERA_SAVE_INTERVAL {
+                self.save_camera();
+            }
+        }
+        if !settled || camera_moved || self.instances_dirty {
+            self.renderer.sync_instances(self.sim.world());
+            self.instances_dirty = false;
+        }
+
+        if self.frame_stats.record(frame_time, physics_steps) {
+            self.renderer.set_stats_text(&format!(
+                "{:.0} FPS ({:.1} ms, {} physics steps/frame)",
+                self.fps(),
+                self.frame_stats.average_frame_time() * 1000.0,
+                physics_steps,
+            ));
+        }
+    }
+
+    /// Frames per second, averaged over the last FRAME_STATS_WINDOW frames
+    pub fn fps(&self) -> f32 {
+        self.frame_stats.fps()
+    }
+
+    /// Average frame time in seconds over the same window as `fps`
+    pub fn frame_time(&self) -> f32 {
+        self.frame_stats.average_frame_time()
+    }
+
+    /// GPU time of the main render pass in milliseconds, None without timestamp query support
+    pub fn last_gpu_frame_ms(&self) -> Option<f32> {
+        self.renderer.last_gpu_frame_ms()
+    }
+
+    /// Set the camera's near and far clip planes, returns false if they're invalid
+    pub fn set_near_far(&mut self, near: f32, far: f32) -> bool {
+        // The culling frustum changes with the planes
+        self.instances_dirty = true;
+        self.renderer.set_near_far(near, far)
+    }
+
+    pub fn near_far(&self) -> (f32, f32) {
+        self.renderer.near_far()
+    }
+
+    /// Release the renderer's GPU resources right away, see `Renderer::destroy`
+    pub fn destroy(mut self, wait: bool) {
+        if self.camera_save_pending {
+            self.save_camera();
+        }
+        self.renderer.destroy(wait);
+    }
+
+    fn save_camera(&mut self) {
+        self.camera_save_pending = false;
+        self.last_camera_save = Instant::now();
+        if let Err(e) = resources::save_setting(CAMERA_SETTING, &self.renderer.serialize_camera()) {
+            log::warn!("Unable to save the camera: {:#}", e);
+        }
+    }
+
+    /// Draw triangles on top of everything, see `Renderer::set_overlay`
+    pub fn set_overlay(&mut self, vertices: &[DebugVertex], indices: &[u32], screen_space: bool) {
+        self.renderer.set_overlay(vertices, indices, screen_space);
+    }
+
+    pub fn clear_overlay(&mut self) {
+        self.renderer.clear_overlay();
+    }
+
+    /// How many fixed physics steps the last update ran, more than one means it was catching up
+    pub fn physics_steps_last_frame(&self) -> u32 {
+        self.frame_stats.physics_steps_last_frame
+    }
+
+    /// Instances drawn last frame, after frustum culling
+    pub fn instance_count(&self) -> usize {
+        self.renderer.instance_count()
+    }
+
+    /// Bodies in the physics world, drawn or not
+    pub fn body_count(&self) -> usize {
+        self.sim.world().get_bodies().len()
+    }
+
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        self.renderer.render()
+    }
+
+    /// Depth format shared by the depth texture and pipelines
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.renderer.depth_format()
+    }
+
+    /// Format of the surface textures, see `Renderer::surface_format`
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.renderer.surface_format()
+    }
+
+    /// The present mode actually in use, which is vsync if uncapped was asked for but isn't supported
+    /// See `Renderer::surface_present_mode`
+    pub fn surface_present_mode(&self) -> wgpu::PresentMode {
+        self.renderer.surface_present_mode()
+    }
+
+    /// Whether the surface handles sRGB encoding, if not colors are gamma corrected in the shaders
+    pub fn is_srgb_surface(&self) -> bool {
+        self.renderer.is_srgb_surface()
+    }
+
+    /// Add a point light, returns false if MAX_LIGHTS are already in the scene
+    pub fn add_light(&mut self, light: Light) -> bool {
+        self.renderer.add_light(light)
+    }
+
+    pub fn clear_lights(&mut self) {
+        self.renderer.clear_lights();
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        self.renderer.lights()
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Renderer drawing into a texture, None when there's no adapter at all (e.g. CI without a software renderer)
+    fn headless_renderer(width: u32, height: u32) -> Option<Renderer> {
+        pollster::block_on(Renderer::new_headless(width, height, &StateBuilder::new())).ok()
+    }
+
+    // Draw a frame with the real `render` and read it back as tightly packed RGBA rows
+    // Fails the test if anything in the frame trips wgpu validation
+    fn render_and_read(renderer: &mut Renderer) -> Vec<u8> {
+        let (width, height) = (renderer.config.width, renderer.config.height);
+        // Rows of a texture copy have to start on 256 byte boundaries
+        let padded_row = (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        renderer.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        renderer.render().unwrap();
+        let mut encoder = renderer.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            renderer.offscreen_texture().unwrap().as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+        renderer.queue.submit(std::iter::once(encoder.finish()));
+        readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        renderer.device.poll(wgpu::PollType::Wait).unwrap();
+        let error = pollster::block_on(renderer.device.pop_error_scope());
+        assert!(error.is_none(), "rendering failed validation: {:?}", error);
+
+        let data = readback.slice(..).get_mapped_range();
+        data.chunks(padded_row as usize)
+            .flat_map(|row| &row[..width as usize * 4])
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn empty_world_renders_without_validation_errors() {
+        let Some(mut renderer) = headless_renderer(64, 64) else {
+            return;
+        };
+        // The bodies' buffer keeps its capacity when they're all removed, a ground without tiles has no bytes at all
+        renderer.set_checkerboard(Some(Checkerboard { tiles: 0, ..Default::default() }));
+        renderer.update(&PhysicsWorld::new(), 0.0);
+
+        let pixels = render_and_read(&mut renderer);
+        assert_eq!(pixels.len(), 64 * 64 * 4);
+    }
+
+    #[test]
+    fn pick_finds_the_body_under_the_pixel() {
+        let Some(mut renderer) = headless_renderer(64, 64) else {
+            return;
+        };
+        let mut world = PhysicsWorld::new();
+        let handle = world.add_cube(cgmath::Vector3::new(0.0, 0.0, 0.0), 1.0);
+        renderer.look_at(cgmath::Point3::new(0.0, 0.0, 5.0), cgmath::Point3::new(0.0, 0.0, 0.0));
+        renderer.update(&world, 0.0);
+
+        // The answer comes back a frame or two after the request, the readback never blocks
+        let pick = |renderer: &mut Renderer, x, y| {
+            renderer.request_pick(x, y);
+            (0..10).find_map(|_| {
+                render_and_read(renderer);
+                renderer.take_pick()
+            })
+        };
+        let center = pick(&mut renderer, 32, 32).expect("pick never resolved");
+        assert_eq!((center.x, center.y, center.body), (32, 32, Some(handle)));
+        let corner = pick(&mut renderer, 0, 0).expect("pick never resolved");
+        assert_eq!(corner.body, None);
+    }
+
+    #[test]
+    fn labels_are_drawn_above_their_body() {
+        let Some(mut renderer) = headless_renderer(64, 64) else {
+            return;
+        };
+        let mut world = PhysicsWorld::new();
+        world.add_cube(cgmath::Vector3::new(0.0, 0.0, 0.0), 1.0);
+        renderer.look_at(cgmath::Point3::new(0.0, 0.0, 8.0), cgmath::Point3::new(0.0, 0.0, 0.0));
+        renderer.update(&world, 0.0);
+        let without = render_and_read(&mut renderer);
+
+        renderer.set_show_labels(true);
+        renderer.sync_instances(&world);
+        let with = render_and_read(&mut renderer);
+
+        let changed_rows: Vec<usize> = (0..64)
+            .filter(|row| {
+                let range = row * 64 * 4..(row + 1) * 64 * 4;
+                without[range.clone()] != with[range]
+            })
+            .collect();
+        assert!(!changed_rows.is_empty(), "the label didn't change the frame");
+        // The cube is centered on the view, its label sits over its top half or above it
+        assert!(changed_rows.iter().all(|row| *row < 32), "label drawn at rows {:?}", changed_rows);
+    }
+
+    #[test]
+    fn stats_text_is_drawn_in_the_top_left_corner() {
+        let Some(mut renderer) = headless_renderer(128, 64) else {
+            return;
+        };
+        renderer.update(&PhysicsWorld::new(), 0.0);
+        let without = render_and_read(&mut renderer);
+
+        renderer.set_stats_text("60 FPS");
+        renderer.set_show_stats(true);
+        let with = render_and_read(&mut renderer);
+
+        let changed: Vec<(usize, usize)> = (0..128 * 64)
+            .filter(|pixel| without[pixel * 4..pixel * 4 + 4] != with[pixel * 4..pixel * 4 + 4])
+            .map(|pixel| (pixel % 128, pixel / 128))
+            .collect();
+        assert!(!changed.is_empty(), "the stats text didn't change the frame");
+        assert!(changed.iter().all(|(x, y)| *x < 64 && *y < 32), "stats drawn at {:?}", changed);
+    }
+
+    #[test]
+    fn instance_buffer_grows_but_never_shrinks() {
+        // Fits, nothing to do
+        assert_eq!(grown_instance_capacity(16, 16, 1000), None);
+        // Too small, doubles until it fits
+        assert_eq!(grown_instance_capacity(16, 17, 1000), Some(32));
+        assert_eq!(grown_instance_capacity(16, 40, 1000), Some(64));
+        // Shrinking keeps the big buffer, only the logical length drops
+        assert_eq!(grown_instance_capacity(64, 3, 1000), None);
+        assert_eq!(grown_instance_capacity(64, 0, 1000), None);
+        // Growing again after a shrink reuses the existing capacity
+        assert_eq!(grown_instance_capacity(64, 50, 1000), None);
+        // Never past what the device can draw
+        assert_eq!(grown_instance_capacity(16, 900, 600), Some(600));
+        // An empty buffer still grows
+        assert_eq!(grown_instance_capacity(0, 5, 1000), Some(8));
+    }
+}
@@ -1,33 +1,49 @@
-use std::io::{BufReader, Cursor};
+use std::collections::HashMap;
+use std::sync::Arc;
 
+use anyhow::Context;
 use wgpu::util::DeviceExt;
 
 use crate::{model, texture};
 
 #[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let mut origin = location.origin().unwrap();
+fn format_url(file_name: &str) -> anyhow::Result<reqwest::Url> {
+    let mut origin = web_sys::window()
+        .and_then(|window| window.location().origin().ok())
+        .context("no page origin to load resources from")?;
     if !origin.ends_with("physicsrenderer") {
         origin = format!("{}/physicsrenderer", origin);
     }
-    let base = reqwest::Url::parse(&format!("{}/", origin,)).unwrap();
-    base.join(file_name).unwrap()
+    let base = reqwest::Url::parse(&format!("{}/", origin,))?;
+    Ok(base.join(file_name)?)
+}
+
+/// Resolve `path` against the current page's URL, the same way the browser resolves a relative link
+#[cfg(target_arch = "wasm32")]
+pub fn page_relative_url(path: &str) -> anyhow::Result<reqwest::Url> {
+    let location = web_sys::window()
+        .and_then(|window| window.location().href().ok())
+        .context("no page location to resolve the url against")?;
+    Ok(reqwest::Url::parse(&location)?.join(path)?)
 }
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     #[cfg(target_arch = "wasm32")]
     let txt = {
-        let url = format_url(file_name);
-        reqwest::get(url).await?.text().await?
+        let url = format_url(file_name)?;
+        reqwest::get(url.clone())
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("fetching {}", url))?
+            .text()
+            .await?
     };
     #[cfg(not(target_arch = "wasm32"))]
     let txt = {
         let path = std::path::Path::new(env!("OUT_DIR"))
             .join("res")
             .join(file_name);
-        std::fs::read_to_string(path)?
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?
     };
 
     Ok(txt)
@@ -36,20 +52,90 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     #[cfg(target_arch = "wasm32")]
     let data = {
-        let url = format_url(file_name);
-        reqwest::get(url).await?.bytes().await?.to_vec()
+        let url = format_url(file_name)?;
+        reqwest::get(url.clone())
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("fetching {}", url))?
+            .bytes()
+            .await?
+            .to_vec()
     };
     #[cfg(not(target_arch = "wasm32"))]
     let data = {
         let path = std::path::Path::new(env!("OUT_DIR"))
             .join("res")
             .join(file_name);
-        std::fs::read(path)?
+        std::fs::read(&path).with_context(|| format!("reading {}", path.display()))?
     };
 
     Ok(data)
 }
 
+// Where native builds keep settings: the platform's per-user config directory, in a folder of our own
+#[cfg(not(target_arch = "wasm32"))]
+fn settings_dir() -> Option<std::path::PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).filter(|dir| !dir.is_empty()).map(std::path::PathBuf::from);
+    let base = if cfg!(target_os = "windows") {
+        env_dir("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME")?.join("Library").join("Application Support")
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".config")))?
+    };
+    Some(base.join("physicsrenderer"))
+}
+
+/// Read a small setting saved by `save_setting` in an earlier session, None if there isn't one
+/// The web build keeps settings in localStorage, native builds in `<name>.json` in the user's config
+/// directory (e.g. ~/.config/physicsrenderer on Linux)
+pub fn load_setting(name: &str) -> Option<String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::window()?.local_storage().ok()??.get_item(name).ok()?
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::read_to_string(settings_dir()?.join(format!("{}.json", name))).ok()
+    }
+}
+
+/// Save a small setting so `load_setting` finds it after a restart or page reload
+pub fn save_setting(name: &str, contents: &str) -> anyhow::Result<()> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .context("localStorage isn't available")?
+            .set_item(name, contents)
+            .map_err(|_| anyhow::anyhow!("writing {} to localStorage", name))
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let dir = settings_dir().context("no config directory, HOME isn't set")?;
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", name));
+        std::fs::write(&path, contents).with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+/// The cube texture used when no other texture is configured
+/// Native builds embed it, the web build fetches it next to the page so it doesn't bloat the .wasm
+pub async fn load_default_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let diffuse_texture = {
+        let diffuse_bytes = include_bytes!("../assets/texture.jpg");
+        texture::Texture::from_bytes(device, queue, diffuse_bytes, "texture.jpg")?
+    };
+    #[cfg(target_arch = "wasm32")]
+    let diffuse_texture = texture::Texture::from_url(device, queue, "assets/texture.jpg", "texture.jpg").await?;
+
+    Ok(diffuse_texture)
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
@@ -59,26 +145,33 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name)
 }
 
+/// Load an OBJ model and the textures its MTL file references
+/// `default_texture` (plain white if `None`) is used for meshes without a material and for
+/// materials whose texture is missing or fails to load
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    default_texture: Option<texture::Texture>,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
+    let (models, obj_materials) = tobj::futures::load_obj_buf(
+        obj_text.as_bytes(),
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap_or_default();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            let p = p.to_string_lossy();
+            // A missing MTL file only costs us the materials, the geometry is still usable
+            let mat_text = load_string(&p).await.unwrap_or_else(|e| {
+                log::warn!("Unable to load material library {}, using the default material: {:#}", p, e);
+                String::new()
+            });
+            tobj::futures::load_mtl_buf(mat_text.as_bytes()).await
         },
     )
     .await?;
@@ -86,7 +179,9 @@ pub async fn load_model(
     let mut materials = Vec::new();
     
     // Create a default material if no materials are provided
-    let default_texture = texture::Texture::create_1x1_texture(device, queue, [255, 255, 255, 255], "default");
+    let default_texture = default_texture.unwrap_or_else(|| {
+        texture::Texture::create_1x1_texture(device, queue, [255, 255, 255, 255], "default")
+    });
     let default_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         layout,
         entries: &[
@@ -110,7 +205,13 @@ pub async fn load_model(
     
     for m in obj_materials? {
         let diffuse_texture = if let Some(diffuse_path) = &m.diffuse_texture {
-            load_texture(diffuse_path, device, queue).await.ok()
+            match load_texture(diffuse_path, device, queue).await {
+                Ok(texture) => Some(texture),
+                Err(e) => {
+                    log::warn!("Unable to load texture {} for material {}: {}", diffuse_path, m.name, e);
+                    None
+                }
+            }
         } else {
             None
         };
@@ -158,7 +259,7 @@ pub async fn load_model(
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| model::ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
@@ -182,26 +283,87 @@ pub async fn load_model(
                 })
                 .collect::<Vec<_>>();
 
+            // Lighting needs real normals, so work them out from the faces if the file has none
+            if m.mesh.normals.is_empty() {
+                model::compute_vertex_normals(&mut vertices, &m.mesh.indices);
+            }
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                // COPY_DST so Model::compute_normals can rewrite it
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             });
+            // Meshes past 65536 vertices get 32-bit indices, the rest 16-bit ones
+            let (index_buffer, index_format) = model::create_index_buffer(device, file_name, &m.mesh.indices);
 
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
+                index_format,
                 num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+                // Slot 0 holds the default material, so MTL materials start at 1
+                material: m.mesh.material_id.map_or(0, |id| id + 1),
+                vertices,
+                indices: m.mesh.indices,
             }
         })
         .collect::<Vec<_>>();
 
     Ok(model::Model { meshes, materials })
-} 
\ No newline at end of file
+} 
+
+/// Caches loaded models and textures by path so each file is only read and parsed once
+#[derive(Default)]
+pub struct ResourceManager {
+    models: HashMap<String, Arc<model::Model>>,
+    textures: HashMap<String, Arc<texture::Texture>>,
+}
+
+impl ResourceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a texture, or return the cached one if `file_name` was loaded before
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Arc<texture::Texture>> {
+        if let Some(texture) = self.textures.get(file_name) {
+            return Ok(texture.clone());
+        }
+
+        let texture = Arc::new(load_texture(file_name, device, queue).await?);
+        self.textures.insert(file_name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    /// Load a model, or return the cached one if `file_name` was loaded before
+    /// The cache is keyed by path only, so `default_texture` only matters on the first load
+    pub async fn load_model(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        default_texture: Option<texture::Texture>,
+    ) -> anyhow::Result<Arc<model::Model>> {
+        if let Some(model) = self.models.get(file_name) {
+            return Ok(model.clone());
+        }
+
+        let model = Arc::new(load_model(file_name, device, queue, layout, default_texture).await?);
+        self.models.insert(file_name.to_string(), model.clone());
+        Ok(model)
+    }
+
+    /// Drop everything from the cache, resources still in use elsewhere stay alive until released
+    pub fn clear(&mut self) {
+        self.models.clear();
+        self.textures.clear();
+    }
+}
@@ -0,0 +1,101 @@
+use cgmath::Vector3;
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::physics::{CubeOptions, PhysicsWorld};
+
+/// Built-in arrangements of bodies for the demo, each spawned on top of the ground
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scene {
+    /// A grid of very bouncy cubes dropped from different heights
+    BouncyCubes,
+    /// A row of dominoes with the first one tipped over
+    Dominoes,
+    /// A pyramid of cubes
+    Pyramid,
+}
+
+impl Scene {
+    /// Every preset, in the order the number keys select them
+    pub const ALL: [Scene; 3] = [Scene::BouncyCubes, Scene::Dominoes, Scene::Pyramid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scene::BouncyCubes => "bouncy cubes",
+            Scene::Dominoes => "dominoes",
+            Scene::Pyramid => "pyramid",
+        }
+    }
+
+    /// How many bodies `spawn` adds
+    pub fn body_count(&self) -> usize {
+        match self {
+            Scene::BouncyCubes => 9,
+            Scene::Dominoes => 12,
+            Scene::Pyramid => 5 + 4 + 3 + 2 + 1,
+        }
+    }
+
+    /// Add this scene's bodies to `world`, returns their handles
+    /// Existing bodies are left alone, see `PhysicsWorld::clear_bodies`
+    pub fn spawn(&self, world: &mut PhysicsWorld) -> Vec<RigidBodyHandle> {
+        let ground_y = world.ground().map(|ground| ground.y).unwrap_or(0.0);
+        match self {
+            Scene::BouncyCubes => {
+                let options = CubeOptions::new().restitution(0.9);
+                let mut handles = Vec::new();
+                for z in 0..3 {
+                    for x in 0..3 {
+                        // Staggered heights so they don't all bounce in sync
+                        let height = ground_y + 3.0 + (x + z * 3) as f32 * 0.5;
+                        let position = Vector3::new((x - 1) as f32 * 2.0, height, (z - 1) as f32 * 2.0);
+                        handles.push(world.add_cube_ex(position, 0.8, options));
+                    }
+                }
+                handles
+            }
+            Scene::Dominoes => {
+                let half_extents = Vector3::new(0.1, 0.75, 0.4);
+                let count = 12;
+                let spacing = 0.9;
+                let start_x = -(count - 1) as f32 * spacing / 2.0;
+                let handles = (0..count)
+                    .map(|i| {
+                        let position = Vector3::new(start_x + i as f32 * spacing, ground_y + half_extents.y, 0.0);
+                        world.add_box(position, half_extents)
+                    })
+                    .collect::<Vec<_>>();
+                // Push the top of the first domino towards the rest to start the chain
+                let top = Vector3::new(start_x, ground_y + half_extents.y * 2.0, 0.0);
+                world.apply_impulse_at_point(handles[0], Vector3::new(0.3, 0.0, 0.0), top);
+                handles
+            }
+            Scene::Pyramid => {
+                let base = 5;
+                let size = 1.0;
+                let mut handles = Vec::new();
+                for row in 0..base {
+                    let width = base - row;
+                    let start_x = -(width - 1) as f32 * size / 2.0;
+                    for i in 0..width {
+                        let position = Vector3::new(start_x + i as f32 * size, ground_y + size / 2.0 + row as f32 * size, 0.0);
+                        handles.push(world.add_cube(position, size));
+                    }
+                }
+                handles
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_count_matches_what_spawn_adds() {
+        for scene in Scene::ALL {
+            let mut world = PhysicsWorld::new();
+            assert_eq!(scene.spawn(&mut world).len(), scene.body_count(), "{}", scene.name());
+        }
+    }
+}
@@ -6,11 +6,43 @@ struct CameraUniform {
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Set by the renderer when the surface isn't sRGB, so we have to gamma encode ourselves
+override apply_gamma: bool = false;
+
 @group(1) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(1) @binding(1)
 var s_diffuse: sampler;
 
+// Must match MAX_LIGHTS in light.rs
+const MAX_LIGHTS: u32 = 8u;
+
+struct Light {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+}
+
+struct Lights {
+    lights: array<Light, MAX_LIGHTS>,
+    count: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+struct Fog {
+    color: vec3<f32>,
+    density: f32, // 0 turns the fog off
+    eye: vec3<f32>,
+}
+
+@group(3) @binding(0)
+var<uniform> fog: Fog;
+
+// Light that reaches every surface when point lights are in use, so unlit sides aren't pitch black
+const AMBIENT: f32 = 0.1;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -23,6 +55,9 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) alpha: f32,
+    @location(10) id: u32, // Picking id, 0 is reserved for "nothing"
+    @location(11) color: vec3<f32>,
 }
 
 //this struct will hold the output of the vertext shader
@@ -32,6 +67,9 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>, //@builtin(position) tells the gpu this is supposed to be the final vertex position
     @location(0) tex_coords: vec2<f32>, // texture coordinates
     @location(1) normal: vec3<f32>, // normal for lighting
+    @location(2) alpha: f32, // per-instance opacity
+    @location(3) color: vec3<f32>, // per-instance tint
+    @location(4) world_position: vec3<f32>, // for light distances
 };
 
 //marks it as an entry point for a vertex shader
@@ -48,13 +86,78 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput
     
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.normal = model.normal;
+    // Rotate the normal into world space (w = 0 skips the translation)
+    // Dividing by the squared scale first keeps it perpendicular to the surface when the scale isn't uniform
+    let scale_sq = vec3<f32>(
+        dot(instance.model_matrix_0.xyz, instance.model_matrix_0.xyz),
+        dot(instance.model_matrix_1.xyz, instance.model_matrix_1.xyz),
+        dot(instance.model_matrix_2.xyz, instance.model_matrix_2.xyz),
+    );
+    out.normal = (model_matrix * vec4<f32>(model.normal / scale_sq, 0.0)).xyz;
+    out.alpha = instance.alpha;
+    out.color = instance.color;
     // Apply the model matrix before the camera view projection
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
+// Sum of every point light's diffuse contribution at a surface point
+// With no lights the scene is drawn fully lit, as it was before lights existed
+fn lighting(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    if lights.count == 0u {
+        return vec3<f32>(1.0);
+    }
+
+    let n = normalize(normal);
+    var total = vec3<f32>(AMBIENT);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i += 1u) {
+        let light = lights.lights[i];
+        let to_light = light.position - position;
+        let distance = length(to_light);
+        let diffuse = max(dot(n, to_light / distance), 0.0);
+        let attenuation = 1.0 / (1.0 + 0.09 * distance + 0.032 * distance * distance);
+        total += light.color * light.intensity * diffuse * attenuation;
+    }
+    return total;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let sampled = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let lit = sampled.rgb * in.color * lighting(in.world_position, in.normal);
+    // Exponential fog, the fraction of light that makes it through falls off with distance
+    let visibility = exp(-fog.density * distance(in.world_position, fog.eye));
+    let fogged = mix(fog.color, lit, visibility);
+    let color = vec4<f32>(fogged, sampled.a * in.alpha);
+    if (apply_gamma) {
+        return vec4<f32>(pow(color.rgb, vec3<f32>(1.0 / 2.2)), color.a);
+    }
+    return color;
+}
+// Picking pass: writes each instance's id so the renderer can read back what's under a pixel
+struct PickOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+@vertex
+fn vs_pick(model: VertexInput, instance: InstanceInput) -> PickOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var out: PickOutput;
+    out.id = instance.id;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_pick(in: PickOutput) -> @location(0) u32 {
+    return in.id;
+}
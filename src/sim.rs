@@ -0,0 +1,156 @@
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::physics::{BodySummary, PhysicsWorld};
+
+/// What a `SimLoop::tick` did
+#[derive(Debug, Clone, Default)]
+pub struct SimTick {
+    /// Every body after the tick
+    pub bodies: Vec<BodySummary>,
+    /// Bodies the kill plane or `PhysicsWorld::set_max_bodies` removed since the last tick
+    pub despawned: Vec<RigidBodyHandle>,
+}
+
+/// Drives a `PhysicsWorld` at a fixed timestep from variable frame times
+/// Has no GPU or window dependency, so the simulation can run headless (e.g. in tests)
+pub struct SimLoop {
+    world: PhysicsWorld,
+    accumulator: f32, // Frame time not yet consumed by fixed physics steps
+    steps_last_run: u32,
+    max_steps_per_run: u32, // Past this, leftover time is dropped instead of caught up
+    behind: bool, // The last run dropped time, so the warning isn't repeated every frame while it lasts
+}
+
+// Default cap on fixed steps per run. A hitch (or a backgrounded web tab) could otherwise bank
+// so much time that catching up makes the next frame slow too, and so on
+const DEFAULT_MAX_STEPS_PER_RUN: u32 = 5;
+
+impl SimLoop {
+    pub fn new(world: PhysicsWorld) -> Self {
+        Self {
+            world,
+            accumulator: 0.0,
+            steps_last_run: 0,
+            max_steps_per_run: DEFAULT_MAX_STEPS_PER_RUN,
+            behind: false,
+        }
+    }
+
+    pub fn world(&self) -> &PhysicsWorld {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut PhysicsWorld {
+        &mut self.world
+    }
+
+    /// Advance by `dt` seconds of frame time and return a snapshot of every body and what was removed
+    pub fn tick(&mut self, dt: f32) -> SimTick {
+        self.run(dt);
+        SimTick {
+            bodies: self.world.body_summaries(),
+            despawned: self.world.take_despawned(),
+        }
+    }
+
+    /// Advance by `dt` seconds of frame time, returns how many fixed steps that took
+    /// Leftover time carries over to the next call. Nothing is stepped while every body is asleep
+    /// Removed bodies are left for `PhysicsWorld::take_despawned`, `tick` collects them instead
+    pub fn run(&mut self, dt: f32) -> u32 {
+        self.steps_last_run = 0;
+        if self.world.all_asleep() {
+            // Don't bank time while idle, or waking up would run a burst of catch-up steps
+            self.accumulator = 0.0;
+            self.behind = false;
+            return 0;
+        }
+
+        let timestep = self.world.timestep();
+        self.accumulator += dt;
+        let mut dropped = false;
+        while self.accumulator >= timestep {
+            if self.steps_last_run >= self.max_steps_per_run {
+                // The simulation falls behind real time rather than freezing the app while it catches up
+                if !self.behind {
+                    log::warn!(
+                        "Physics fell behind, dropping {:.0} ms after {} steps",
+                        self.accumulator * 1000.0,
+                        self.steps_last_run,
+                    );
+                }
+                self.accumulator = 0.0;
+                dropped = true;
+                break;
+            }
+            self.world.step(timestep);
+            self.accumulator -= timestep;
+            self.steps_last_run += 1;
+        }
+        if self.behind && !dropped {
+            log::info!("Physics caught up with real time");
+        }
+        self.behind = dropped;
+        self.steps_last_run
+    }
+
+    /// Most fixed steps a single `run` takes, any time left over after that is discarded
+    pub fn set_max_steps_per_run(&mut self, max_steps: u32) {
+        self.max_steps_per_run = max_steps.max(1);
+    }
+
+    pub fn max_steps_per_run(&self) -> u32 {
+        self.max_steps_per_run
+    }
+
+    /// Fixed steps taken by the last `run` or `tick`
+    pub fn steps_last_run(&self) -> u32 {
+        self.steps_last_run
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    #[test]
+    fn tick_carries_leftover_time() {
+        let mut world = PhysicsWorld::new();
+        world.add_cube(Vector3::new(0.0, 10.0, 0.0), 1.0);
+        let timestep = world.timestep();
+        let mut sim = SimLoop::new(world);
+
+        // Two and a half steps of time runs two, the half step is kept for the next tick
+        let tick = sim.tick(timestep * 2.5);
+        assert_eq!(sim.steps_last_run(), 2);
+        assert_eq!(tick.bodies.len(), 1);
+        assert!(tick.bodies[0].position.y < 10.0);
+
+        sim.tick(timestep * 0.6);
+        assert_eq!(sim.steps_last_run(), 1);
+    }
+
+    #[test]
+    fn tick_reports_despawned_bodies() {
+        let mut world = PhysicsWorld::new();
+        world.set_kill_plane(Some(0.0));
+        let cube = world.add_cube(Vector3::new(0.0, -1.0, 0.0), 1.0);
+        let timestep = world.timestep();
+        let mut sim = SimLoop::new(world);
+
+        let tick = sim.tick(timestep);
+        assert!(tick.bodies.is_empty());
+        assert_eq!(tick.despawned, vec![cube]);
+        assert!(sim.tick(timestep).despawned.is_empty());
+    }
+
+    #[test]
+    fn empty_world_still_steps() {
+        let world = PhysicsWorld::new();
+        let timestep = world.timestep();
+        let mut sim = SimLoop::new(world);
+
+        assert_eq!(sim.run(timestep * 2.0), 2);
+        assert_eq!(sim.world().step_count(), 2);
+    }
+}
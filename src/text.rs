@@ -0,0 +1,316 @@
+use glyph_brush::ab_glyph::FontArc;
+use glyph_brush::{
+    BrushAction, BrushError, GlyphBrush, GlyphBrushBuilder, GlyphVertex, HorizontalAlign, Layout, Section, Text,
+    VerticalAlign,
+};
+
+use crate::renderer::ColorSpace;
+
+// Bundled so text looks the same on every platform and needs no font lookup on the web, see res/fonts/LICENSE
+const FONT: &[u8] = include_bytes!("../res/fonts/DejaVuSansMono.ttf");
+
+// Side of the glyph cache texture to start with, it grows when the queued glyphs don't fit
+const INITIAL_CACHE_SIZE: u32 = 256;
+
+/// One glyph quad in pixels, with the part of the glyph cache it samples
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    left_top: [f32; 2],
+    right_bottom: [f32; 2],
+    tex_left_top: [f32; 2],
+    tex_right_bottom: [f32; 2],
+    color: [f32; 4],
+}
+
+impl GlyphInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Float32x2, 4 => Float32x4
+    ];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+    // Sections are never given bounds, so glyphs don't need clipping to them
+    fn from_vertex(vertex: GlyphVertex) -> Self {
+        Self {
+            left_top: [vertex.pixel_coords.min.x, vertex.pixel_coords.min.y],
+            right_bottom: [vertex.pixel_coords.max.x, vertex.pixel_coords.max.y],
+            tex_left_top: [vertex.tex_coords.min.x, vertex.tex_coords.min.y],
+            tex_right_bottom: [vertex.tex_coords.max.x, vertex.tex_coords.max.y],
+            color: vertex.extra.color,
+        }
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Glyph Instance Buffer"),
+        size: (capacity * std::mem::size_of::<GlyphInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Holds coverage only, one byte per texel
+fn create_cache_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Glyph Cache Texture"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn create_cache_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &wgpu::Texture,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("glyph_cache_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+/// Text drawn in screen space with a pixel-space camera, laid out and rasterized by glyph_brush
+/// Queue text, then `upload` it before the frame is rendered. Only what was queued since the last upload is drawn
+pub struct TextRenderer {
+    brush: GlyphBrush<GlyphInstance>,
+    pipeline: wgpu::RenderPipeline,
+    cache_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    cache_texture: wgpu::Texture,
+    cache_bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    capacity: usize, // How many glyphs buffer can hold
+    uploaded: usize, // How many glyphs the last upload wrote
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        color_space: ColorSpace,
+    ) -> anyhow::Result<Self> {
+        let font = FontArc::try_from_slice(FONT)?;
+        let brush = GlyphBrushBuilder::using_font(font)
+            .initial_cache_size((INITIAL_CACHE_SIZE, INITIAL_CACHE_SIZE))
+            .build();
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Text Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
+        });
+
+        let cache_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("glyph_cache_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &cache_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[GlyphInstance::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &[("apply_gamma", if color_space == ColorSpace::Linear { 1.0 } else { 0.0 })],
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip, // four vertices per glyph quad
+                ..Default::default()
+            },
+            // Drawn in its own pass after the scene, nothing should hide it
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph Cache Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let cache_texture = create_cache_texture(device, INITIAL_CACHE_SIZE, INITIAL_CACHE_SIZE);
+        let cache_bind_group = create_cache_bind_group(device, &cache_bind_group_layout, &cache_texture, &sampler);
+
+        let capacity = 64;
+        Ok(Self {
+            brush,
+            pipeline,
+            cache_bind_group_layout,
+            sampler,
+            cache_texture,
+            cache_bind_group,
+            buffer: create_instance_buffer(device, capacity),
+            capacity,
+            uploaded: 0,
+        })
+    }
+
+    /// Queue a single line of text centered on `center`, in pixels with y pointing down
+    /// `height` is the font size in pixels
+    pub fn centered(&mut self, center: (f32, f32), height: f32, text: &str, color: [f32; 4]) {
+        let layout = Layout::default_single_line()
+            .h_align(HorizontalAlign::Center)
+            .v_align(VerticalAlign::Center);
+        self.brush.queue(
+            Section::default()
+                .with_screen_position(center)
+                .with_layout(layout)
+                .add_text(Text::new(text).with_scale(height).with_color(color)),
+        );
+    }
+
+    /// Queue a single line of text with its top left corner at `position`, in pixels with y pointing down
+    pub fn top_left(&mut self, position: (f32, f32), height: f32, text: &str, color: [f32; 4]) {
+        self.brush.queue(
+            Section::default()
+                .with_screen_position(position)
+                .with_layout(Layout::default_single_line())
+                .add_text(Text::new(text).with_scale(height).with_color(color)),
+        );
+    }
+
+    /// Lay out everything queued since the last upload and copy it to the GPU
+    /// New glyphs are rasterized into the cache, which grows if they don't fit
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        loop {
+            let texture = &self.cache_texture;
+            let result = self.brush.process_queued(
+                |rect, data| {
+                    queue.write_texture(
+                        wgpu::TexelCopyTextureInfo {
+                            aspect: wgpu::TextureAspect::All,
+                            texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d { x: rect.min[0], y: rect.min[1], z: 0 },
+                        },
+                        data,
+                        wgpu::TexelCopyBufferLayout {
+                            offset: 0,
+                            bytes_per_row: Some(rect.width()),
+                            rows_per_image: Some(rect.height()),
+                        },
+                        wgpu::Extent3d { width: rect.width(), height: rect.height(), depth_or_array_layers: 1 },
+                    );
+                },
+                GlyphInstance::from_vertex,
+            );
+
+            match result {
+                Ok(BrushAction::Draw(instances)) => {
+                    self.write_instances(device, queue, &instances);
+                    return;
+                }
+                // Same text as last time, the buffer already holds it
+                Ok(BrushAction::ReDraw) => return,
+                Err(BrushError::TextureTooSmall { suggested: (width, height) }) => {
+                    let max = device.limits().max_texture_dimension_2d;
+                    if (width.min(max), height.min(max)) == self.brush.texture_dimensions() {
+                        log::warn!("Too much text to fit in the glyph cache, skipping it this frame");
+                        self.uploaded = 0;
+                        return;
+                    }
+                    self.resize_cache(device, width.min(max), height.min(max));
+                }
+            }
+        }
+    }
+
+    // Replace the cache texture, glyph_brush forgets what it cached and rasterizes everything again
+    fn resize_cache(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.cache_texture.destroy();
+        self.cache_texture = create_cache_texture(device, width, height);
+        self.cache_bind_group =
+            create_cache_bind_group(device, &self.cache_bind_group_layout, &self.cache_texture, &self.sampler);
+        self.brush.resize_texture(width, height);
+    }
+
+    fn write_instances(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[GlyphInstance]) {
+        if instances.len() > self.capacity {
+            let mut capacity = self.capacity.max(1);
+            while capacity < instances.len() {
+                capacity *= 2;
+            }
+            self.buffer = create_instance_buffer(device, capacity);
+            self.capacity = capacity;
+        }
+        if !instances.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        }
+        self.uploaded = instances.len();
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.uploaded == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.cache_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..4, 0..self.uploaded as u32);
+    }
+}
@@ -0,0 +1,53 @@
+// Screen-space text, one instanced quad per glyph sampled from the glyph cache
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var t_glyphs: texture_2d<f32>;
+@group(1) @binding(1)
+var s_glyphs: sampler;
+
+// Set by the renderer when the surface isn't sRGB, so we have to gamma encode ourselves
+override apply_gamma: bool = false;
+
+struct GlyphInput {
+    @location(0) left_top: vec2<f32>,
+    @location(1) right_bottom: vec2<f32>,
+    @location(2) tex_left_top: vec2<f32>,
+    @location(3) tex_right_bottom: vec2<f32>,
+    @location(4) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, glyph: GlyphInput) -> VertexOutput {
+    // Corners in triangle strip order: left top, right top, left bottom, right bottom
+    let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+    let position = mix(glyph.left_top, glyph.right_bottom, corner);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.tex_coords = mix(glyph.tex_left_top, glyph.tex_right_bottom, corner);
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The cache only stores coverage, the color comes from the glyph
+    let coverage = textureSample(t_glyphs, s_glyphs, in.tex_coords).r;
+    var color = in.color.rgb;
+    if apply_gamma {
+        color = pow(color, vec3<f32>(1.0 / 2.2));
+    }
+    return vec4<f32>(color, in.color.a * coverage);
+}
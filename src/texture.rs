@@ -10,9 +10,24 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-    
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    /// Depth formats to try, most preferred first
+    pub const DEPTH_FORMATS: [wgpu::TextureFormat; 2] = [
+        wgpu::TextureFormat::Depth32Float,
+        wgpu::TextureFormat::Depth24Plus,
+    ];
+
+    /// Pick the first depth format in `DEPTH_FORMATS` the adapter can render to and sample
+    /// The same format has to be used for the depth texture and the pipeline's `DepthStencilState`
+    pub fn select_depth_format(adapter: &wgpu::Adapter) -> Result<wgpu::TextureFormat> {
+        let required = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        Self::DEPTH_FORMATS
+            .iter()
+            .copied()
+            .find(|format| adapter.get_texture_format_features(*format).allowed_usages.contains(required))
+            .ok_or_else(|| anyhow!("adapter supports none of the depth formats {:?}", Self::DEPTH_FORMATS))
+    }
+
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat, label: &str) -> Self {
         let size = wgpu::Extent3d {
             width: config.width.max(1),
             height: config.height.max(1),
@@ -24,7 +39,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
@@ -60,6 +75,36 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    /// Read and decode an image file from disk
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &std::path::Path,
+        label: &str
+    ) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("reading texture {}", path.display()))?;
+        Self::from_bytes(device, queue, &bytes, label)
+    }
+
+    /// Fetch and decode an image, relative URLs are resolved against the current page
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_url(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        url: &str,
+        label: &str
+    ) -> Result<Self> {
+        let url = crate::resources::page_relative_url(url)?;
+        let bytes = reqwest::get(url.clone()).await?
+            .error_for_status()
+            .with_context(|| format!("fetching texture {}", url))?
+            .bytes()
+            .await?;
+        Self::from_bytes(device, queue, &bytes, label)
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
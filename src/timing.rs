@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// One timestamp at the start of the render pass and one at the end
+const QUERY_COUNT: u32 = 2;
+const RESULTS_SIZE: wgpu::BufferAddress = QUERY_COUNT as wgpu::BufferAddress * 8;
+
+/// Measures how long the render pass takes on the GPU with timestamp queries
+/// Results are read back asynchronously, so the reported time is a frame or two old
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period: f32, // Nanoseconds per timestamp tick
+    copied: bool, // resolve copied results that haven't been mapped yet
+    in_flight: bool, // readback_buffer is mapped or being mapped, don't copy into it
+    mapped: Arc<AtomicBool>, // Set by the map_async callback
+    last_ms: Option<f32>,
+}
+
+impl GpuTimer {
+    /// None when the device wasn't created with `Features::TIMESTAMP_QUERY`
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timestamp Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size: RESULTS_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size: RESULTS_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            copied: false,
+            in_flight: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            last_ms: None,
+        })
+    }
+
+    /// Passed to the render pass so the GPU records when it starts and ends
+    pub fn timestamp_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Pick up the result of an earlier frame if it's ready, then copy this frame's timestamps for reading
+    /// Call after the render pass ends and before the encoder is submitted
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.in_flight && self.mapped.swap(false, Ordering::Acquire) {
+            {
+                let data = self.readback_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                // The end can read lower than the start if the GPU clock was reset in between
+                let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                self.last_ms = Some(ticks as f32 * self.period / 1_000_000.0);
+            }
+            self.readback_buffer.unmap();
+            self.in_flight = false;
+        }
+        // Skip frames while a readback is pending, the buffer can't be written while mapped
+        if self.in_flight {
+            return;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, RESULTS_SIZE);
+        self.copied = true;
+    }
+
+    /// Start reading back what `resolve` copied, call after the encoder is submitted
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.in_flight = true;
+        let mapped = self.mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            if result.is_ok() {
+                mapped.store(true, Ordering::Release);
+            }
+        });
+    }
+
+    /// GPU time of the render pass in milliseconds, from the most recent frame that finished reading back
+    pub fn last_ms(&self) -> Option<f32> {
+        self.last_ms
+    }
+}